@@ -0,0 +1,207 @@
+use crate::journal;
+use crate::parser;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* support bundles
+Diagnostics are often collected into a tar archive, compressed with any of the formats the readers detect, or a zip
+archive. The members are read into memory instead of being extracted:
+- ARCHIVE:PATH reads the member at PATH, an argument naming an existing file is never split at a colon
+- an archive given by itself is scanned for the members that look like journal data, exports start with the
+  __CURSOR= field journalctl writes first and journal files with their signature. Only the start of the other
+  members is read, so large logs and core dumps in a bundle don't have to fit in memory.
+- members can be compressed themselves, and are detected the same way files are
+*/
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+// Offset and value of the magic field of the ustar header, including the old GNU tar variant
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_BLOCK_SIZE: usize = 512;
+
+// Bytes of a member looked at to decide if it's journal data
+const SNIFF_LEN: u64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+/// A file read out of an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    /// Path of the member within the archive
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Split an ARCHIVE:PATH argument into the archive and the path of the member
+pub fn split_member(arg: &str) -> Option<(&str, &str)> {
+    if Path::new(arg).exists() {
+        return None;
+    }
+
+    arg.match_indices(':')
+        .map(|(i, _)| (&arg[..i], &arg[i + 1..]))
+        .find(|(archive, _)| Path::new(archive).is_file())
+}
+
+/// Detect tar archives, compressed or not, and zip archives by their contents
+pub fn archive_format(path: &str) -> io::Result<Option<ArchiveFormat>> {
+    let mut file = File::open(path)?;
+
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    file.by_ref().take(ZIP_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    if magic == ZIP_MAGIC {
+        return Ok(Some(ArchiveFormat::Zip));
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(TAR_BLOCK_SIZE);
+    parser::decompress(Box::new(file))?
+        .take(TAR_BLOCK_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    match header.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) {
+        Some(magic) if magic == TAR_MAGIC => Ok(Some(ArchiveFormat::Tar)),
+        _ => Ok(None),
+    }
+}
+
+/// Read the member named by path out of the archive, or every member that looks like journal data without a path
+pub fn read_members(archive: &str, format: ArchiveFormat, path: Option<&str>) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+
+    let mut add = |name: &str, reader: &mut dyn Read| -> io::Result<()> {
+        // tar archives created from a directory list their members as ./name
+        let name = name.trim_start_matches("./");
+        if path.is_some_and(|path| path.trim_start_matches("./") != name) {
+            return Ok(());
+        }
+
+        // the start of the member read to recognize it is kept, the rest is only read for journal data
+        let mut recorder = Recorder { reader, data: Vec::new() };
+        if path.is_none() && !is_journal_data(&mut recorder) {
+            return Ok(());
+        }
+
+        let mut data = recorder.data;
+        reader.read_to_end(&mut data)?;
+        members.push(Member {
+            name: name.to_owned(),
+            data,
+        });
+        Ok(())
+    };
+
+    match format {
+        ArchiveFormat::Tar => {
+            let mut tar = tar::Archive::new(parser::decompress(Box::new(File::open(archive)?))?);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+
+                let name = entry.path()?.to_string_lossy().into_owned();
+                add(&name, &mut entry)?;
+            }
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if !file.is_file() {
+                    continue;
+                }
+
+                let name = file.name().to_owned();
+                add(&name, &mut file)?;
+            }
+        }
+    }
+
+    Ok(members)
+}
+
+// Check for the start of a journal export or journal file, after decompressing the member if needed
+fn is_journal_data(reader: &mut dyn Read) -> bool {
+    let mut start = Vec::new();
+    let read = parser::decompress(Box::new(reader)).and_then(|reader| reader.take(SNIFF_LEN).read_to_end(&mut start));
+
+    read.is_ok() && (start.starts_with(b"__CURSOR=") || journal::is_journal_magic(&start))
+}
+
+// Reader keeping a copy of the data read through it
+struct Recorder<'a> {
+    reader: &'a mut dyn Read,
+    data: Vec<u8>,
+}
+
+impl Read for Recorder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[test]
+fn read_members_test() {
+    let export = include_bytes!("../assets/journal.export.3.example");
+    let compressed = include_bytes!("../assets/journal.export.3.example.zst");
+
+    // node1/system.export, node2/system.export.zst and a README, as written by tar czf and zip
+    for (archive, format) in &[
+        ("assets/bundle.example.tar.gz", ArchiveFormat::Tar),
+        ("assets/bundle.example.zip", ArchiveFormat::Zip),
+    ] {
+        assert_eq!(archive_format(archive).unwrap(), Some(*format));
+
+        let members = read_members(archive, *format, None).unwrap();
+        let names: Vec<&str> = members.iter().map(|member| member.name.as_str()).collect();
+        assert_eq!(names, vec!["node1/system.export", "node2/system.export.zst"]);
+        assert_eq!(members[0].data, &export[..]);
+        assert_eq!(members[1].data, &compressed[..]);
+
+        // a path selects the member even if it doesn't look like an export
+        let members = read_members(archive, *format, Some("./README")).unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].name, "README");
+        assert!(read_members(archive, *format, Some("node3/system.export")).unwrap().is_empty());
+    }
+
+    assert_eq!(archive_format("assets/journal.export.3.example.gz").unwrap(), None);
+}
+
+#[test]
+fn sniff_member_test() {
+    // recognizing a member only reads the start of it
+    let mut large = io::repeat(b'x').take(1 << 30);
+    let mut recorder = Recorder {
+        reader: &mut large,
+        data: Vec::new(),
+    };
+    assert!(!is_journal_data(&mut recorder));
+    assert!(recorder.data.len() < 64 * 1024);
+
+    let compressed = include_bytes!("../assets/journal.export.3.example.zst");
+    assert!(is_journal_data(&mut &compressed[..]));
+    assert!(!is_journal_data(&mut &b"MESSAGE=no cursor\n\n"[..]));
+}
+
+#[test]
+fn split_member_test() {
+    assert_eq!(
+        split_member("assets/bundle.example.tar.gz:node1/system.export"),
+        Some(("assets/bundle.example.tar.gz", "node1/system.export"))
+    );
+    assert_eq!(split_member("assets/bundle.example.tar.gz"), None);
+    assert_eq!(split_member("missing.tar.gz:node1/system.export"), None);
+}
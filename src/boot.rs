@@ -0,0 +1,299 @@
+use crate::output;
+use crate::parser::{JournalMessage, OutputMode};
+use crate::zone::Zone;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(test)]
+use crate::parser::{Filter, JournalBackupReader};
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+#[cfg(test)]
+use structopt::StructOpt;
+
+/* journalctl boot selection
+Entries are grouped into boots by their _BOOT_ID, and the boots are ordered by their first entry. Boots are
+selected the same way as journalctl -b:
+- 0 or no value is the last boot, -1 the boot before it and so on
+- positive offsets count from the start, 1 is the first boot
+- a 32 character boot id selects that boot, and can be followed by an offset relative to it, ID-1
+- -b only takes the next argument as its value when it is a boot descriptor, so -b node.export reads the file
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boot {
+    pub boot_id: String,
+    /// Realtime timestamp of the first and last entry of the boot in microseconds
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Collect the boots of the entries, ordered by the time of their first entry
+pub fn list_boots<I: IntoIterator<Item = JournalMessage>>(messages: I) -> Vec<Boot> {
+    let mut boots: Vec<Boot> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for msg in messages {
+        let (boot_id, realtime) = match (msg.field(b"_BOOT_ID"), msg.timestamp().journal_realtime_usec()) {
+            (Some(boot_id), Some(realtime)) => (boot_id, realtime),
+            _ => continue,
+        };
+
+        match index.get(&boot_id) {
+            Some(&i) => {
+                let boot = &mut boots[i];
+                boot.first = boot.first.min(realtime);
+                boot.last = boot.last.max(realtime);
+            }
+            None => {
+                index.insert(boot_id.clone(), boots.len());
+                boots.push(Boot {
+                    boot_id,
+                    first: realtime,
+                    last: realtime,
+                });
+            }
+        }
+    }
+
+    boots.sort_by_key(|boot| boot.first);
+    boots
+}
+
+/// Render the boots as the journalctl --list-boots table, the last boot has index 0
+pub fn format_boots(boots: &[Boot], zone: &Zone) -> String {
+    let mut rows = vec![[
+        "IDX".to_owned(),
+        "BOOT ID".to_owned(),
+        "FIRST ENTRY".to_owned(),
+        "LAST ENTRY".to_owned(),
+    ]];
+
+    for (i, boot) in boots.iter().enumerate() {
+        rows.push([
+            (i as i64 + 1 - boots.len() as i64).to_string(),
+            boot.boot_id.clone(),
+            output::format_realtime(boot.first, OutputMode::short_full, zone).unwrap_or_default(),
+            output::format_realtime(boot.last, OutputMode::short_full, zone).unwrap_or_default(),
+        ]);
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    for row in &rows {
+        // the index is right aligned, and the last column isn't padded
+        out += &format!(
+            "{:>w0$} {:w1$} {:w2$} {}\n",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+    out
+}
+
+/// Join -b and --boot with the next argument when it parses as a boot descriptor, the way journalctl peeks at it.
+/// The option only takes a value given with =, any other argument following it is left for the files and matches.
+pub fn boot_args<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut args = args.into_iter().peekable();
+    let mut joined = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            joined.push(arg);
+            joined.extend(args);
+            break;
+        }
+
+        let value = match arg.to_str() {
+            Some("-b") | Some("--boot") => match args.peek().and_then(|next| next.to_str()) {
+                Some(next) if !next.is_empty() && next.parse::<BootSpec>().is_ok() => {
+                    let value = next.to_owned();
+                    args.next();
+                    Some(value)
+                }
+                _ => None,
+            },
+            // a value attached to the short option, -b-1
+            Some(arg) if arg.starts_with("-b") && !arg.starts_with("-b=") => Some(arg[2..].to_owned()),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => joined.push(format!("--boot={}", value).into()),
+            None => joined.push(arg),
+        }
+    }
+
+    joined
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BootSpec {
+    Offset(i64),
+    Id(String, i64),
+}
+
+impl BootSpec {
+    /// Find the boot id selected by the spec in the boot list
+    pub fn resolve(&self, boots: &[Boot]) -> Result<String, String> {
+        let index = match self {
+            BootSpec::Offset(offset) if *offset > 0 => Some(offset - 1),
+            BootSpec::Offset(offset) => Some(boots.len() as i64 - 1 + offset),
+            BootSpec::Id(id, offset) => boots
+                .iter()
+                .position(|boot| boot.boot_id == *id)
+                .map(|i| i as i64 + offset),
+        };
+
+        match index {
+            Some(i) if i >= 0 && (i as usize) < boots.len() => Ok(boots[i as usize].boot_id.clone()),
+            _ => Err(format!("Data from the specified boot ({}) is not available", self)),
+        }
+    }
+}
+
+impl Default for BootSpec {
+    fn default() -> Self {
+        BootSpec::Offset(0)
+    }
+}
+
+impl FromStr for BootSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Failed to parse boot descriptor '{}'", s);
+
+        if s.is_empty() {
+            return Ok(BootSpec::default());
+        }
+
+        if s.len() >= 32 && s.is_char_boundary(32) && s[..32].chars().all(|c| c.is_ascii_hexdigit()) {
+            let offset = match &s[32..] {
+                "" => 0,
+                offset if offset.starts_with(['+', '-']) => {
+                    offset.parse().map_err(|_| invalid())?
+                }
+                _ => return Err(invalid()),
+            };
+            return Ok(BootSpec::Id(s[..32].to_ascii_lowercase(), offset));
+        }
+
+        s.parse().map(BootSpec::Offset).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for BootSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootSpec::Offset(offset) => write!(f, "{:+}", offset),
+            BootSpec::Id(id, 0) => write!(f, "{}", id),
+            BootSpec::Id(id, offset) => write!(f, "{}{:+}", id, offset),
+        }
+    }
+}
+
+#[cfg(test)]
+fn boots_example() -> Vec<Boot> {
+    let data = include_bytes!("../assets/journal.export.boots.example");
+    list_boots(JournalBackupReader::new(Box::new(&data[..]), None))
+}
+
+#[test]
+fn list_boots_test() {
+    assert_eq!(
+        format_boots(&boots_example(), &Zone::Utc),
+        "\
+IDX BOOT ID                          FIRST ENTRY                 LAST ENTRY
+ -2 1b7d2b24b6f14dd29c6f4a0b4ea1c001 Wed 2020-10-14 04:33:20 UTC Wed 2020-10-14 04:33:25 UTC
+ -1 2c8e3c35c7025ee3ad705b1c5fb2d002 Wed 2020-10-14 05:33:20 UTC Wed 2020-10-14 05:33:25 UTC
+  0 3d9f4d46d8136ff4be816c2d60c3e003 Wed 2020-10-14 06:33:20 UTC Wed 2020-10-14 06:33:30 UTC
+"
+    );
+    assert_eq!(format_boots(&[], &Zone::Utc), "IDX BOOT ID FIRST ENTRY LAST ENTRY\n");
+}
+
+#[test]
+fn parse_boot_spec_test() {
+    let id = "2c8e3c35c7025ee3ad705b1c5fb2d002";
+
+    assert_eq!("".parse(), Ok(BootSpec::Offset(0)));
+    assert_eq!("-1".parse(), Ok(BootSpec::Offset(-1)));
+    assert_eq!("+2".parse(), Ok(BootSpec::Offset(2)));
+    assert_eq!(id.parse(), Ok(BootSpec::Id(id.to_owned(), 0)));
+    assert_eq!(format!("{}-1", id).parse(), Ok(BootSpec::Id(id.to_owned(), -1)));
+    assert_eq!(BootSpec::Id(id.to_owned(), 1).to_string(), format!("{}+1", id));
+    assert!("last".parse::<BootSpec>().is_err());
+    assert!(format!("{}1", id).parse::<BootSpec>().is_err());
+}
+
+#[test]
+fn resolve_test() {
+    let boots = boots_example();
+    let resolve = |s: &str| s.parse::<BootSpec>().unwrap().resolve(&boots);
+
+    assert_eq!(resolve("0").unwrap(), "3d9f4d46d8136ff4be816c2d60c3e003");
+    assert_eq!(resolve("-2").unwrap(), "1b7d2b24b6f14dd29c6f4a0b4ea1c001");
+    assert_eq!(resolve("1").unwrap(), "1b7d2b24b6f14dd29c6f4a0b4ea1c001");
+    assert_eq!(
+        resolve("1b7d2b24b6f14dd29c6f4a0b4ea1c001+1").unwrap(),
+        "2c8e3c35c7025ee3ad705b1c5fb2d002"
+    );
+    assert!(resolve("-3").is_err());
+    assert!(resolve("4").is_err());
+    assert!(resolve("ffffffffffffffffffffffffffffffff").is_err());
+}
+
+#[test]
+fn boot_filter_test() {
+    let data = include_bytes!("../assets/journal.export.boots.example");
+    let messages = |args: &[&str]| -> Vec<String> {
+        let mut filter = Filter::from_iter(boot_args(args.iter().map(OsString::from)));
+        filter.select_boot(&boots_example()).unwrap();
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter))
+            .map(|msg| msg.field(b"MESSAGE").unwrap())
+            .collect()
+    };
+
+    assert_eq!(messages(&["jrnlb"]).len(), 7);
+    assert_eq!(
+        messages(&["jrnlb", "-b"]),
+        vec!["Linux version 5.8.0", "panic: out of memory", "Stopping app.service..."]
+    );
+    assert_eq!(messages(&["jrnlb", "-b", "-1"]), vec!["Linux version 5.8.0", "panic: out of memory"]);
+    assert_eq!(messages(&["jrnlb", "-b-1"]), vec!["Linux version 5.8.0", "panic: out of memory"]);
+    assert_eq!(
+        messages(&["jrnlb", "--boot=1b7d2b24b6f14dd29c6f4a0b4ea1c001"]),
+        vec!["Linux version 5.8.0", "Started Journal Service."]
+    );
+}
+
+#[test]
+fn boot_args_test() {
+    let args = |args: &[&str]| -> Vec<OsString> { boot_args(args.iter().map(OsString::from)) };
+    let id = "2c8e3c35c7025ee3ad705b1c5fb2d002";
+
+    // the argument following -b is only taken when it is a boot descriptor
+    assert_eq!(args(&["jrnlb", "--boot", "node.export"]), vec!["jrnlb", "--boot", "node.export"]);
+    assert_eq!(args(&["jrnlb", "-b", "node.export"]), vec!["jrnlb", "-b", "node.export"]);
+    assert_eq!(args(&["jrnlb", "-b", "-1", "node.export"]), vec!["jrnlb", "--boot=-1", "node.export"]);
+    assert_eq!(args(&["jrnlb", "--boot", id]), vec!["jrnlb", &format!("--boot={}", id)]);
+    assert_eq!(args(&["jrnlb", "-b", "-r"]), vec!["jrnlb", "-b", "-r"]);
+    assert_eq!(args(&["jrnlb", "-b", ""]), vec!["jrnlb", "-b", ""]);
+    assert_eq!(args(&["jrnlb", "-b2"]), vec!["jrnlb", "--boot=2"]);
+    assert_eq!(args(&["jrnlb", "-b=2", "--boot=1"]), vec!["jrnlb", "-b=2", "--boot=1"]);
+    assert_eq!(args(&["jrnlb", "--", "-b", "1"]), vec!["jrnlb", "--", "-b", "1"]);
+}
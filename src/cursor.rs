@@ -0,0 +1,157 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* journal cursor format
+A cursor is a list of key=value pairs separated by ';' describing the location of an entry in the journal:
+s= the sequence number id of the journal file the entry was written to
+i= the sequence number of the entry within that sequence number id (hex)
+b= the boot id the entry was written in
+m= the monotonic timestamp of the entry within the boot (hex)
+t= the realtime timestamp of the entry (hex)
+x= the xor hash of the entry data (hex)
+Any of the keys may be missing, unknown keys are ignored.
+*/
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cursor {
+    pub seqnum_id: Option<String>,
+    pub seqnum: Option<u64>,
+    pub boot_id: Option<String>,
+    pub monotonic: Option<u64>,
+    pub realtime: Option<u64>,
+    pub xor_hash: Option<u64>,
+}
+
+impl Cursor {
+    /// Compare the position of two entries the same way the journal orders them, by sequence number when both are
+    /// from the same sequence, by monotonic time within the same boot, and by realtime otherwise. Returns None when
+    /// the cursors don't carry enough information to be compared.
+    pub fn cmp_position(&self, other: &Cursor) -> Option<Ordering> {
+        if self.seqnum_id.is_some() && self.seqnum_id == other.seqnum_id {
+            if let (Some(a), Some(b)) = (self.seqnum, other.seqnum) {
+                return Some(a.cmp(&b));
+            }
+        }
+
+        if self.boot_id.is_some() && self.boot_id == other.boot_id {
+            if let (Some(a), Some(b)) = (self.monotonic, other.monotonic) {
+                return Some(a.cmp(&b));
+            }
+        }
+
+        match (self.realtime, other.realtime) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cursor = Cursor::default();
+
+        for item in s.trim().split(';').filter(|item| !item.is_empty()) {
+            let (key, value) = match item.find('=') {
+                Some(i) => (&item[..i], &item[i + 1..]),
+                None => return Err(format!("invalid cursor item '{}'", item)),
+            };
+
+            let hex = |v: &str| {
+                u64::from_str_radix(v, 16).map_err(|e| format!("invalid cursor item '{}': {}", item, e))
+            };
+
+            match key {
+                "s" => cursor.seqnum_id = Some(value.to_owned()),
+                "i" => cursor.seqnum = Some(hex(value)?),
+                "b" => cursor.boot_id = Some(value.to_owned()),
+                "m" => cursor.monotonic = Some(hex(value)?),
+                "t" => cursor.realtime = Some(hex(value)?),
+                "x" => cursor.xor_hash = Some(hex(value)?),
+                _ => (),
+            }
+        }
+
+        if cursor == Cursor::default() {
+            return Err(format!("invalid cursor '{}'", s));
+        }
+
+        Ok(cursor)
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = Vec::new();
+
+        if let Some(s) = &self.seqnum_id {
+            items.push(format!("s={}", s));
+        }
+        if let Some(i) = self.seqnum {
+            items.push(format!("i={:x}", i));
+        }
+        if let Some(b) = &self.boot_id {
+            items.push(format!("b={}", b));
+        }
+        if let Some(m) = self.monotonic {
+            items.push(format!("m={:x}", m));
+        }
+        if let Some(t) = self.realtime {
+            items.push(format!("t={:x}", t));
+        }
+        if let Some(x) = self.xor_hash {
+            items.push(format!("x={:x}", x));
+        }
+
+        write!(f, "{}", items.join(";"))
+    }
+}
+
+#[test]
+fn parse_cursor_test() {
+    let s = "s=4d4c07169cf346bf84c0682dee9f876d;i=f7101;b=29afc66917be48d58ba2a628b946422c;m=a2531317;t=5ae0622d0fbb8;x=966282a14870533f";
+    let cursor: Cursor = s.parse().unwrap();
+
+    assert_eq!(
+        cursor,
+        Cursor {
+            seqnum_id: Some("4d4c07169cf346bf84c0682dee9f876d".to_owned()),
+            seqnum: Some(0xf7101),
+            boot_id: Some("29afc66917be48d58ba2a628b946422c".to_owned()),
+            monotonic: Some(2723353367),
+            realtime: Some(1598716260711352),
+            xor_hash: Some(0x966282a14870533f),
+        }
+    );
+    assert_eq!(cursor.to_string(), s);
+
+    assert_eq!("t=5ae0622d0fbb8".parse::<Cursor>().unwrap().realtime, Some(1598716260711352));
+    assert!("".parse::<Cursor>().is_err());
+    assert!("garbage".parse::<Cursor>().is_err());
+    assert!("i=xyz".parse::<Cursor>().is_err());
+}
+
+#[test]
+fn cmp_position_test() {
+    let a: Cursor = "s=aa;i=10;b=bb;m=5;t=100".parse().unwrap();
+
+    // same sequence, ordered by seqnum even if the clock went backwards
+    let b: Cursor = "s=aa;i=11;b=bb;m=6;t=50".parse().unwrap();
+    assert_eq!(a.cmp_position(&b), Some(Ordering::Less));
+
+    // different sequence in the same boot, ordered by monotonic time
+    let c: Cursor = "s=cc;i=1;b=bb;m=4;t=200".parse().unwrap();
+    assert_eq!(a.cmp_position(&c), Some(Ordering::Greater));
+
+    // different sequence and boot, ordered by realtime
+    let d: Cursor = "s=cc;i=1;b=dd;m=1;t=100".parse().unwrap();
+    assert_eq!(a.cmp_position(&d), Some(Ordering::Equal));
+
+    let e: Cursor = "s=cc;i=1".parse().unwrap();
+    assert_eq!(a.cmp_position(&e), None);
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Error reading a journal export or journal file, with the position in the (decompressed) input it was found at
+#[derive(Debug)]
+pub struct JournalError {
+    /// Byte offset of the data that couldn't be read
+    pub offset: u64,
+    /// Index of the entry being read, counting every entry in the input including filtered ones
+    pub entry: u64,
+    pub kind: JournalErrorKind,
+}
+
+#[derive(Debug)]
+pub enum JournalErrorKind {
+    /// Reading the input failed, reading can be retried
+    Io(io::Error),
+    /// The data isn't in the journal export format
+    Parse(String),
+    /// An object of a journal file is damaged or points outside of the file
+    Corrupt(String),
+    /// An entry grew beyond the size limit without being terminated
+    EntryTooLarge(usize),
+    /// The input ended in the middle of an entry
+    Truncated { bytes: u64 },
+    /// Invalid data was skipped to resume reading at the next entry, reading continues after this error
+    Skipped { bytes: u64, entries: u64 },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            JournalErrorKind::Io(e) => write!(f, "read error: {}", e)?,
+            JournalErrorKind::Parse(e) => write!(f, "invalid journal export data ({})", e)?,
+            JournalErrorKind::Corrupt(e) => write!(f, "corrupt journal file ({})", e)?,
+            JournalErrorKind::EntryTooLarge(limit) => write!(f, "entry larger than {} bytes", limit)?,
+            JournalErrorKind::Truncated { bytes } => write!(f, "truncated entry of {} bytes", bytes)?,
+            JournalErrorKind::Skipped { bytes, entries } => {
+                let plural = if *entries == 1 { "entry" } else { "entries" };
+                write!(f, "skipped {} bytes and {} {} of invalid data", bytes, entries, plural)?
+            }
+        }
+        write!(f, " at byte {} in entry {}", self.offset, self.entry)
+    }
+}
+
+impl Error for JournalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            JournalErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -1,3 +1,13 @@
 extern crate chrono_english;
 extern crate nom;
+pub mod archive;
+pub mod boot;
+pub mod cursor;
+pub mod error;
+pub mod journal;
+pub mod matcher;
+pub mod merge;
+pub mod output;
 pub mod parser;
+pub mod template;
+pub mod zone;
@@ -1,48 +1,597 @@
-mod parser;
-
-use parser::JournalBackupReader;
-use std::io::{self, ErrorKind, Write};
+use jrnlb::archive;
+use jrnlb::boot;
+use jrnlb::error::{JournalError, JournalErrorKind};
+use jrnlb::journal::{self, JournalFile, JournalFileReader};
+use jrnlb::matcher::Matches;
+use jrnlb::merge::MergedReader;
+use jrnlb::output::OutputOptions;
+use jrnlb::parser::{self, Follow, JournalBackupReader, JournalMessage};
+use jrnlb::zone::Zone;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+use std::io::{self, ErrorKind, IsTerminal, Read, Write};
 use structopt::StructOpt;
 
+// How often to check for new entries when following files
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
 /// This doc string acts as a help message when the user runs '--help'
 /// as do all doc strings on fields
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(version = "0.1.0", author = "Kevin Nisbet <kevin@xybyte.com>")]
+#[structopt(setting = structopt::clap::AppSettings::AllowNegativeNumbers)]
 struct Opts {
     #[structopt(flatten)]
     filter: parser::Filter,
 
-    /// Journal export files to parse
+    /// Show the newest entries first
+    #[structopt(short, long)]
+    reverse: bool,
+
+    /// Show the last 10 entries, or as many as --lines, and keep printing new entries as the export files grow
+    #[structopt(short, long, conflicts_with = "reverse")]
+    follow: bool,
+
+    /// Stop reading a file at the first invalid or truncated entry, instead of skipping to the next entry. Without
+    /// it the exit status is 2 when invalid data was skipped.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Show the boots found in the input, numbered relative to the last boot
+    #[structopt(long)]
+    list_boots: bool,
+
+    /// Show the files that would be read, including the members found in tar and zip archives
+    #[structopt(long)]
+    list_sources: bool,
+
+    /// Read the journal files in the directory and its subdirectories, like a copy of /var/log/journal. Files
+    /// journald marked as corrupt (.journal~) are skipped.
+    #[structopt(short = "D", long, number_of_values = 1)]
+    directory: Vec<String>,
+
+    /// Read the files matching the glob pattern, quoted to keep the shell from expanding it
+    #[structopt(long = "file", number_of_values = 1)]
+    file_patterns: Vec<String>,
+
+    /// Journal export or journal files to parse, reads from stdin when no files are given or the file is -. The
+    /// entries of multiple files are merged by timestamp. Tar and zip archives are searched for exports, or read
+    /// ARCHIVE:PATH to pick a member. Arguments of the form FIELD=VALUE filter the entries like journalctl
+    /// matches, with + separating alternatives.
     files: Vec<String>,
 
-    /// Change journal output mode
-    #[structopt(short, long = "output", possible_values = &parser::OutputMode::variants(), case_insensitive = true)]
-    pub output_mode: Option<parser::OutputMode>,
+    #[structopt(flatten)]
+    output: OutputOptions,
 }
 
 fn main() {
-    let opts: Opts = Opts::from_args();
+    let mut opts = Opts::from_iter(boot::boot_args(std::env::args_os()));
     //println!("{:?}", opts);
 
-    let mut line_count = 0;
+    opts.output.zone = match opts.output.tz {
+        _ if opts.output.utc => Zone::Utc,
+        Some(zone) => zone,
+        None => Zone::local(),
+    };
 
-    for file in opts.clone().files {
-        for msg in JournalBackupReader::open_file(file, Some(opts.filter.clone())).unwrap() {
-            if let Err(e) = io::stdout().write_all(msg.to_string(opts.clone().output_mode).as_bytes()) {
-                match e.kind() {
-                    ErrorKind::BrokenPipe => return,
-                    _ => {
-                        eprintln!("write to stdout failed: {:?}", e);
-                    }
-                }
+    let zone = opts.output.zone;
+    let prepared = opts
+        .filter
+        .load_cursor_file()
+        .and_then(|_| opts.filter.compile_grep())
+        .and_then(|_| opts.filter.parse_dates(&zone));
+    if let Err(e) = prepared {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    opts.output.color = io::stdout().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none()
+        && std::env::var("TERM").map_or(true, |term| term != "dumb");
+    opts.output.columns = columns();
+
+    let mut last_cursor = None;
+    let mut newest_cursor = None;
+
+    let (matches, mut files): (Vec<String>, Vec<String>) = opts
+        .files
+        .iter()
+        .cloned()
+        .partition(|arg| Matches::is_match_arg(arg));
+
+    opts.filter.matches = match Matches::parse(&matches) {
+        Ok(matches) => matches,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match find_files(&opts) {
+        Ok((found, corrupt)) => {
+            for file in corrupt {
+                eprintln!("skipping {}: marked as corrupt by journald", file);
+            }
+            files.extend(found);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if files.is_empty() {
+        files.push("-".to_owned());
+    }
+
+    let mut sources = match find_sources(&files) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if opts.list_sources {
+        for source in &sources {
+            println!("{}", source.name());
+        }
+        return;
+    }
+
+    // stdin and archive members don't grow, following them would wait for new entries forever. Journal files are
+    // mapped once when they're opened, the entries journald adds later wouldn't be seen.
+    if opts.follow {
+        if let Some(source) = sources.iter().find(|source| !matches!(source, Source::File(_))) {
+            eprintln!("--follow only works with files, not {}", source.name());
+            std::process::exit(1);
+        }
+        if let Some(source) = sources.iter().find(|source| source.is_journal_file()) {
+            eprintln!("--follow only works with export files, not the journal file {}", source.name());
+            std::process::exit(1);
+        }
+    }
+
+    let errors = Errors::default();
+
+    // boots are listed in a first pass over the input, so stdin is buffered to be able to read it twice
+    let needs_boots = opts.list_boots || opts.filter.boot_requested();
+    if needs_boots {
+        for source in sources.iter_mut().filter(|source| matches!(source, Source::Stdin)) {
+            let mut data = Vec::new();
+            if let Err(e) = io::stdin().read_to_end(&mut data) {
+                eprintln!("failed to read stdin: {}", e);
+                std::process::exit(1);
             }
+            *source = Source::Memory {
+                name: "stdin".to_owned(),
+                data: data.into(),
+            };
+        }
 
-            line_count+=1;
-            if let Some(line_limit) = opts.filter.clone().lines {
-                if line_count == line_limit {
-                    return
+        let boots = source_boots(&sources, &opts);
+
+        if opts.list_boots {
+            print!("{}", boot::format_boots(&boots, &opts.output.zone));
+            return;
+        }
+
+        if let Err(e) = opts.filter.select_boot(&boots) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // the entries of all the files are merged into a single timeline
+    let readers: Vec<_> = sources
+        .iter()
+        .map(|source| open(source, Some(opts.filter.clone()), &opts, Some(&errors)))
+        .collect();
+
+    // following starts with the newest entries, like tail -f
+    let lines = match opts.follow {
+        true => opts.filter.lines.or(Some(10)),
+        false => opts.filter.lines,
+    };
+
+    let mut merged = MergedReader::new(readers);
+    let messages: Box<dyn Iterator<Item = JournalMessage>> = if opts.follow {
+        Box::new(tail(merged.by_ref(), lines).into_iter().chain(Follow::new(merged, FOLLOW_INTERVAL)))
+    } else if opts.reverse {
+        Box::new(tail(merged, lines).into_iter().rev())
+    } else if lines.is_some() {
+        Box::new(tail(merged, lines).into_iter())
+    } else {
+        Box::new(merged)
+    };
+
+    for msg in messages {
+        if let Err(e) = io::stdout().write_all(&msg.format(&opts.output)) {
+            match e.kind() {
+                ErrorKind::BrokenPipe => return,
+                _ => {
+                    eprintln!("write to stdout failed: {:?}", e);
                 }
             }
         }
+
+        // entries without a cursor keep the cursor of the entry before them
+        if let Some(cursor) = msg.field(b"__CURSOR") {
+            // the next run resumes after the newest entry, which is shown first in reverse
+            if !opts.reverse || newest_cursor.is_none() {
+                newest_cursor = Some(cursor.clone());
+            }
+            last_cursor = Some(cursor);
+        }
+    }
+
+    if let Some(cursor) = last_cursor {
+        if opts.filter.show_cursor {
+            println!("-- cursor: {}", cursor);
+        }
+    }
+
+    if let (Some(cursor), Some(cursor_file)) = (newest_cursor, &opts.filter.cursor_file) {
+        if let Err(e) = std::fs::write(cursor_file, cursor) {
+            eprintln!("failed to write cursor file {}: {}", cursor_file, e);
+            std::process::exit(1);
+        }
+    }
+
+    if errors.failed.get() {
+        std::process::exit(1);
+    }
+    if errors.skipped.get() > 0 {
+        std::process::exit(2);
+    }
+}
+
+// Width the messages are ellipsized to with --no-full, taken from COLUMNS or the terminal like journalctl does
+fn columns() -> usize {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|c| *c > 0) {
+        return columns;
+    }
+
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+
+    80
+}
+
+// The journal files of the --directory and --file options, and separately the ones marked as corrupt that are skipped
+fn find_files(opts: &Opts) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut found = Vec::new();
+
+    for directory in &opts.directory {
+        let files = journal::find_journal_files(directory)
+            .map_err(|e| format!("failed to read directory {}: {}", directory, e))?;
+        if files.is_empty() {
+            return Err(format!("no journal files found in {}", directory));
+        }
+        found.extend(files);
+    }
+
+    for pattern in &opts.file_patterns {
+        let paths = glob::glob(pattern).map_err(|e| format!("invalid --file pattern {}: {}", pattern, e))?;
+        let mut matched = false;
+        for path in paths {
+            let path = path.map_err(|e| format!("failed to read {}: {}", e.path().display(), e.error()))?;
+            found.push(path.to_string_lossy().into_owned());
+            matched = true;
+        }
+        if !matched {
+            return Err(format!("no files match {}", pattern));
+        }
+    }
+
+    Ok(found.into_iter().partition(|file| !journal::is_marked_corrupt(file)))
+}
+
+// An input to read entries from
+enum Source {
+    Stdin,
+    File(String),
+    // stdin read ahead of time, or a member of an archive
+    Memory { name: String, data: Rc<[u8]> },
+}
+
+impl Source {
+    fn name(&self) -> &str {
+        match self {
+            Source::Stdin => "stdin",
+            Source::File(file) => file,
+            Source::Memory { name, .. } => name,
+        }
+    }
+
+    // Files that can't be opened are reported when they're read
+    fn is_journal_file(&self) -> bool {
+        match self {
+            Source::File(file) => journal::is_journal_file(file).unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+// Turn the files into sources, archives are replaced by the members selected or found in them
+fn find_sources(files: &[String]) -> Result<Vec<Source>, String> {
+    let mut sources = Vec::new();
+
+    for file in files {
+        if file == "-" {
+            sources.push(Source::Stdin);
+            continue;
+        }
+
+        let (path, member) = match archive::split_member(file) {
+            Some((path, member)) => (path, Some(member)),
+            None => (file.as_str(), None),
+        };
+
+        // files that can't be opened are reported when they're read
+        let format = match archive::archive_format(path) {
+            Ok(Some(format)) => format,
+            Ok(None) if member.is_some() => return Err(format!("{} is not a tar or zip archive", path)),
+            _ => {
+                sources.push(Source::File(file.clone()));
+                continue;
+            }
+        };
+
+        let members = archive::read_members(path, format, member).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        if members.is_empty() {
+            match member {
+                Some(member) => return Err(format!("{} not found in {}", member, path)),
+                None => eprintln!("no journal exports found in {}", path),
+            }
+        }
+
+        sources.extend(members.into_iter().map(|member| Source::Memory {
+            name: format!("{}:{}", path, member.name),
+            data: member.data.into(),
+        }));
+    }
+
+    Ok(sources)
+}
+
+// What went wrong reading the sources, the errors themselves are printed as they happen
+#[derive(Default)]
+struct Errors {
+    // times invalid data was skipped
+    skipped: Cell<u64>,
+    // a source couldn't be read completely
+    failed: Cell<bool>,
+}
+
+// The boots of the sources, read in a first pass that leaves reporting errors to the pass showing the entries
+fn source_boots(sources: &[Source], opts: &Opts) -> Vec<boot::Boot> {
+    boot::list_boots(sources.iter().flat_map(|source| open(source, None, opts, None)))
+}
+
+// Open a source for reading, errors reading the source are reported and end its entries, skipped invalid data is
+// only reported
+fn open<'a>(
+    source: &'a Source,
+    filter: Option<parser::Filter>,
+    opts: &Opts,
+    errors: Option<&'a Errors>,
+) -> impl Iterator<Item = JournalMessage> + 'a {
+    let name = source.name();
+
+    let results = match open_results(source, filter, opts) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("failed to open {}: {}", name, e);
+            std::process::exit(1);
+        }
+    };
+
+    // not fused, a reader that ran out of data has more once a followed file grows
+    let mut results = results;
+    let mut stopped = false;
+    std::iter::from_fn(move || loop {
+        if stopped {
+            return None;
+        }
+
+        let e = match results.next()? {
+            Ok(msg) => return Some(msg),
+            Err(e) => e,
+        };
+
+        let skipped = matches!(e.kind, JournalErrorKind::Skipped { .. });
+        if let Some(errors) = errors {
+            eprintln!("{}: {}", name, e);
+            if skipped {
+                errors.skipped.set(errors.skipped.get() + 1);
+            } else {
+                errors.failed.set(true);
+            }
+        }
+        stopped = !skipped;
+    })
+}
+
+// Journal files are recognized by their signature and read natively, any other input is read as an export
+fn open_results(
+    source: &Source,
+    filter: Option<parser::Filter>,
+    opts: &Opts,
+) -> io::Result<Box<dyn Iterator<Item = Result<JournalMessage, JournalError>>>> {
+    let native = match source {
+        Source::Stdin => None,
+        Source::File(file) if journal::is_journal_file(file)? => Some(JournalFile::open(file)?),
+        Source::Memory { data, .. } if journal::is_journal_magic(data) => {
+            Some(JournalFile::new(Box::new(data.clone()))?)
+        }
+        Source::Memory { data, .. } if journal::is_journal_reader(Box::new(&data[..]))? => {
+            Some(JournalFile::decompress(Box::new(&data[..]))?)
+        }
+        _ => None,
+    };
+
+    if let Some(file) = native {
+        let mut reader = JournalFileReader::new(file, filter);
+        reader.set_recover(!opts.strict);
+        return Ok(Box::new(reader.results()));
+    }
+
+    let mut reader = match source {
+        Source::Stdin => JournalBackupReader::open_stdin(filter)?,
+        Source::File(file) => JournalBackupReader::open_file(file.to_owned(), filter)?,
+        Source::Memory { data, .. } => JournalBackupReader::open_reader(Box::new(io::Cursor::new(data.clone())), filter)?,
+    };
+    reader.set_recover(!opts.strict);
+    reader.set_follow(opts.follow);
+    Ok(Box::new(reader.results()))
+}
+
+// Keep the last lines entries, or all of them without a limit. The input is read to the end, since the newest
+// entries are last and compressed input can't be read backwards.
+fn tail<I: Iterator<Item = JournalMessage>>(messages: I, lines: Option<u64>) -> VecDeque<JournalMessage> {
+    let mut buffer = VecDeque::new();
+
+    for msg in messages {
+        buffer.push_back(msg);
+
+        if let Some(lines) = lines {
+            if buffer.len() as u64 > lines {
+                buffer.pop_front();
+            }
+        }
+    }
+
+    buffer
+}
+
+#[test]
+fn follow_appended_test() {
+    let path = std::env::temp_dir().join(format!("jrnlb-follow-{}.export", std::process::id()));
+    std::fs::write(&path, "__CURSOR=1\n__REALTIME_TIMESTAMP=1\nMESSAGE=one\n\n").unwrap();
+
+    let opts = Opts::from_iter(&["jrnlb", "-f"]);
+    let source = Source::File(path.to_string_lossy().into_owned());
+    let errors = Errors::default();
+    let mut merged = MergedReader::new(vec![open(&source, None, &opts, Some(&errors))]);
+
+    let messages: Vec<_> = merged.by_ref().map(|msg| msg.message()).collect();
+    assert_eq!(messages, vec![Some("one".to_owned())]);
+    assert!(merged.next().is_none());
+
+    // entries written after the end of the file was reached are read on the next poll
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"__CURSOR=2\n__REALTIME_TIMESTAMP=2\nMESSAGE=two\n\n").unwrap();
+    assert_eq!(merged.next().map(|msg| msg.message()), Some(Some("two".to_owned())));
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(errors.skipped.get(), 0);
+    assert!(!errors.failed.get());
+}
+
+#[test]
+fn boot_errors_reported_once_test() {
+    let path = std::env::temp_dir().join(format!("jrnlb-boots-{}.export", std::process::id()));
+    let entry = |i: u64| format!("__CURSOR={}\n__REALTIME_TIMESTAMP={}\n_BOOT_ID={:032}\nMESSAGE=m\n\n", i, i, 1);
+    std::fs::write(&path, format!("{}=invalid\n\n{}", entry(1), entry(2))).unwrap();
+
+    // the invalid entry is skipped in the pass finding the boots and the pass showing the entries
+    let mut opts = Opts::from_iter(&["jrnlb", "-b", path.to_str().unwrap()]);
+    let sources = vec![Source::File(path.to_string_lossy().into_owned())];
+    let boots = source_boots(&sources, &opts);
+    assert_eq!(boots.len(), 1);
+    opts.filter.select_boot(&boots).unwrap();
+
+    let errors = Errors::default();
+    assert_eq!(open(&sources[0], Some(opts.filter.clone()), &opts, Some(&errors)).count(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(errors.skipped.get(), 1);
+    assert!(!errors.failed.get());
+}
+
+#[test]
+fn directory_test() {
+    use flate2::read::GzDecoder;
+
+    // a copy of /var/log/journal with the active and a rotated journal, and one journald set aside as corrupt
+    let directory = std::env::temp_dir().join(format!("jrnlb-directory-{}", std::process::id()));
+    let machine = directory.join("3d1219c7c4c5404aaa1f6d2a48adfda4");
+    std::fs::create_dir_all(&machine).unwrap();
+    let write = |name: &str, compressed: &[u8]| {
+        let mut data = Vec::new();
+        GzDecoder::new(compressed).read_to_end(&mut data).unwrap();
+        std::fs::write(machine.join(name), data).unwrap();
+    };
+    write("system.journal", include_bytes!("../assets/journal.native.compact.example.gz"));
+    write(
+        "system@1861bb2ce7ab46eaa816db935fed8c84-0000000000000001-00065dfaf0360604.journal",
+        include_bytes!("../assets/journal.native.regular.example.gz"),
+    );
+    write("system@00065dfabe88b5bd-e0ce39f8cf363447.journal~", include_bytes!("../assets/journal.native.modes.example.gz"));
+
+    let opts = Opts::from_iter(&["jrnlb", "-D", directory.to_str().unwrap()]);
+    let (files, corrupt) = find_files(&opts).unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(corrupt.len(), 1);
+    assert!(corrupt[0].ends_with(".journal~"));
+
+    let sources = find_sources(&files).unwrap();
+    let errors = Errors::default();
+    let readers = sources.iter().map(|source| open(source, None, &opts, Some(&errors))).collect();
+    let messages: Vec<JournalMessage> = MergedReader::new(readers).collect();
+    let counts: Vec<usize> = sources.iter().map(|source| open(source, None, &opts, None).count()).collect();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    // the entries of both files interleaved by time, the sequence number ids of the cursors tell the files apart
+    assert_eq!(messages.len(), counts.iter().sum::<usize>());
+    let realtime = |msg: &JournalMessage| msg.field(b"__REALTIME_TIMESTAMP").unwrap().parse::<u64>().unwrap();
+    assert!(messages.windows(2).all(|pair| realtime(&pair[0]) <= realtime(&pair[1])));
+    let seqnum_ids: std::collections::HashSet<String> =
+        messages.iter().map(|msg| msg.field(b"__CURSOR").unwrap()[..34].to_owned()).collect();
+    assert_eq!(seqnum_ids.len(), 2);
+    assert_eq!(errors.skipped.get(), 0);
+    assert!(!errors.failed.get());
+}
+
+#[test]
+fn compressed_journal_test() {
+    use flate2::read::GzDecoder;
+
+    let compressed: &[u8] = include_bytes!("../assets/journal.native.compact.example.gz");
+    let mut data = Vec::new();
+    GzDecoder::new(compressed).read_to_end(&mut data).unwrap();
+    let expected = JournalFileReader::new(JournalFile::new(Box::new(data)).unwrap(), None).count();
+
+    // a journal file compressed as a whole, given directly and as a member of an archive
+    let path = std::env::temp_dir().join(format!("jrnlb-compressed-{}.tar", std::process::id()));
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(compressed.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "node1/system.journal.gz", compressed).unwrap();
+    std::fs::write(&path, builder.into_inner().unwrap()).unwrap();
+
+    let files = vec!["assets/journal.native.compact.example.gz".to_owned(), path.to_string_lossy().into_owned()];
+    let sources = find_sources(&files).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(sources.len(), 2);
+    assert!(sources[1].name().ends_with("node1/system.journal.gz"));
+
+    let opts = Opts::from_iter(&["jrnlb"]);
+    let errors = Errors::default();
+    for source in &sources {
+        assert_eq!(open(source, None, &opts, Some(&errors)).count(), expected);
     }
+    assert!(expected > 0);
+    assert_eq!(errors.skipped.get(), 0);
+    assert!(!errors.failed.get());
 }
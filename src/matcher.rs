@@ -0,0 +1,149 @@
+use crate::parser::JournalMessage;
+
+#[cfg(test)]
+use crate::parser::JournalBackupReader;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* journalctl match expressions
+Positional FIELD=VALUE arguments are matched against the fields of each entry:
+- terms for the same field are OR'd, so _PID=1 _PID=2 shows entries from either pid
+- terms for different fields are AND'd, so _PID=1 PRIORITY=3 shows entries from pid 1 with priority 3
+- + separates groups of terms, an entry is shown when it matches any of the groups
+Values are compared byte for byte against the raw field data, so binary serialized values match as well.
+*/
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Matches {
+    groups: Vec<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl Matches {
+    /// Check if a positional argument is part of a match expression rather than a file
+    pub fn is_match_arg(arg: &str) -> bool {
+        if arg == "+" {
+            return true;
+        }
+
+        match arg.find('=') {
+            Some(i) => field_name_valid(&arg[..i]),
+            None => false,
+        }
+    }
+
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Matches, String> {
+        let mut matches = Matches::default();
+        let mut group = Vec::new();
+
+        for arg in args.iter().map(|arg| arg.as_ref()) {
+            if arg == "+" {
+                if group.is_empty() {
+                    return Err("\"+\" can only be used between terms".to_owned());
+                }
+                matches.groups.push(group);
+                group = Vec::new();
+                continue;
+            }
+
+            match arg.find('=') {
+                Some(i) if field_name_valid(&arg[..i]) => {
+                    let (field, value) = arg.as_bytes().split_at(i);
+                    group.push((field.to_vec(), value[1..].to_vec()))
+                }
+                _ => return Err(format!("invalid match '{}'", arg)),
+            }
+        }
+
+        if group.is_empty() {
+            if !matches.groups.is_empty() {
+                return Err("\"+\" can only be used between terms".to_owned());
+            }
+        } else {
+            matches.groups.push(group);
+        }
+
+        Ok(matches)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Evaluate the expression against a message, an empty expression matches everything
+    pub fn matches(&self, msg: &JournalMessage) -> bool {
+        self.is_empty() || self.groups.iter().any(|group| group_matches(group, msg))
+    }
+}
+
+fn group_matches(group: &[(Vec<u8>, Vec<u8>)], msg: &JournalMessage) -> bool {
+    // every field named in the group needs one of its terms to match
+    group.iter().all(|(field, _)| {
+        group
+            .iter()
+            .filter(|(f, _)| f == field)
+            .any(|(_, value)| msg.fields_named(field).any(|v| v == &value[..]))
+    })
+}
+
+// Field names are made of upper case letters, digits and underscores, and don't start with a digit
+fn field_name_valid(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[test]
+fn is_match_arg_test() {
+    assert!(Matches::is_match_arg("_SYSTEMD_UNIT=sshd.service"));
+    assert!(Matches::is_match_arg("MESSAGE="));
+    assert!(Matches::is_match_arg("+"));
+    assert!(!Matches::is_match_arg("/tmp/journal.export"));
+    assert!(!Matches::is_match_arg("foo=bar.export"));
+    assert!(!Matches::is_match_arg("1FOO=bar"));
+    assert!(!Matches::is_match_arg("-"));
+}
+
+#[test]
+fn parse_test() {
+    assert_eq!(Matches::parse::<&str>(&[]).unwrap(), Matches::default());
+    assert_eq!(
+        Matches::parse(&["A=1", "B=2=3", "+", "A=4"]).unwrap(),
+        Matches {
+            groups: vec![
+                vec![(b"A".to_vec(), b"1".to_vec()), (b"B".to_vec(), b"2=3".to_vec())],
+                vec![(b"A".to_vec(), b"4".to_vec())],
+            ]
+        }
+    );
+    assert!(Matches::parse(&["+", "A=1"]).is_err());
+    assert!(Matches::parse(&["A=1", "+"]).is_err());
+    assert!(Matches::parse(&["A=1", "+", "+", "B=1"]).is_err());
+    assert!(Matches::parse(&["a=1"]).is_err());
+}
+
+#[test]
+fn matches_test() {
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let pids = |args: &[&str]| -> Vec<String> {
+        let matches = Matches::parse(args).unwrap();
+        JournalBackupReader::new(Box::new(&data[..]), None)
+            .filter(|msg| matches.matches(msg))
+            .map(|msg| msg.pid())
+            .collect()
+    };
+
+    assert_eq!(pids(&[]), vec!["654", "654", "590"]);
+    assert_eq!(pids(&["_PID=590"]), vec!["590"]);
+    // same field OR'd
+    assert_eq!(pids(&["_PID=590", "_PID=654"]), vec!["654", "654", "590"]);
+    // different fields AND'd
+    assert_eq!(pids(&["_PID=590", "_COMM=rsyslogd"]), Vec::<String>::new());
+    assert_eq!(pids(&["_PID=654", "_COMM=rsyslogd", "_PID=1"]), vec!["654", "654"]);
+    // disjunction of groups
+    assert_eq!(pids(&["_PID=590", "_COMM=rsyslogd", "+", "CODE_LINE=1049"]), vec!["590"]);
+    assert_eq!(pids(&["_PID=1", "+", "_COMM=rsyslogd"]), vec!["654", "654"]);
+    // values must match exactly, binary serialized ones included
+    assert_eq!(pids(&["_SELINUX_CONTEXT=unconfined"]), Vec::<String>::new());
+    assert_eq!(pids(&["_SELINUX_CONTEXT=unconfined\n"]), vec!["654", "654", "590"]);
+}
@@ -0,0 +1,172 @@
+use crate::parser::JournalMessage;
+use std::cmp::Ordering;
+
+#[cfg(test)]
+use crate::parser::JournalBackupReader;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* merging journal exports
+Exports taken from several nodes, or overlapping exports of the same journal, are merged into a single timeline:
+- each input is expected to be in journal order already, so only the next entry of every input is compared
+- entries are ordered by the time the journal received them, with the cursor breaking ties between entries of the same journal
+- entries with the same __CURSOR as an entry already shown are dropped, overlapping exports share identical
+  entries which always have the same timestamp, so only the cursors shown for the current timestamp are kept
+*/
+
+pub struct MergedReader<I: Iterator<Item = JournalMessage>> {
+    readers: Vec<I>,
+    heads: Vec<Option<JournalMessage>>,
+
+    last_realtime: Option<u64>,
+    last_cursors: Vec<Vec<u8>>,
+}
+
+impl<I: Iterator<Item = JournalMessage>> MergedReader<I> {
+    pub fn new(readers: Vec<I>) -> MergedReader<I> {
+        let heads = readers.iter().map(|_| None).collect();
+
+        MergedReader {
+            readers,
+            heads,
+            last_realtime: None,
+            last_cursors: Vec::new(),
+        }
+    }
+
+    // Index of the input holding the earliest entry, inputs listed first win ties
+    fn earliest(&mut self) -> Option<usize> {
+        for (reader, head) in self.readers.iter_mut().zip(self.heads.iter_mut()) {
+            if head.is_none() {
+                *head = reader.next();
+            }
+        }
+
+        let mut earliest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let msg = match head {
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            earliest = match earliest {
+                Some(e) if cmp_entries(msg, self.heads[e].as_ref().unwrap()) != Ordering::Less => Some(e),
+                _ => Some(i),
+            };
+        }
+
+        earliest
+    }
+
+    fn is_duplicate(&mut self, msg: &JournalMessage) -> bool {
+        let cursor = match msg.field_bytes(b"__CURSOR") {
+            Some(cursor) => cursor,
+            None => return false,
+        };
+
+        let realtime = msg.timestamp().journal_realtime_usec();
+        if realtime != self.last_realtime {
+            self.last_realtime = realtime;
+            self.last_cursors.clear();
+        }
+
+        if self.last_cursors.iter().any(|c| c == cursor) {
+            return true;
+        }
+
+        self.last_cursors.push(cursor.to_vec());
+        false
+    }
+}
+
+impl<I: Iterator<Item = JournalMessage>> Iterator for MergedReader<I> {
+    type Item = JournalMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let i = self.earliest()?;
+            let msg = self.heads[i].take().unwrap();
+
+            if !self.is_duplicate(&msg) {
+                return Some(msg);
+            }
+        }
+    }
+}
+
+fn cmp_entries(a: &JournalMessage, b: &JournalMessage) -> Ordering {
+    let realtime = |msg: &JournalMessage| msg.timestamp().journal_realtime_usec();
+
+    realtime(a).cmp(&realtime(b)).then_with(|| {
+        match (a.cursor(), b.cursor()) {
+            (Some(a), Some(b)) => a.cmp_position(&b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        }
+    })
+}
+
+#[cfg(test)]
+fn example_reader(entries: &[&[u8]]) -> JournalBackupReader {
+    let data: Vec<u8> = entries.iter().flat_map(|entry| entry.iter().chain(b"\n\n")).copied().collect();
+    JournalBackupReader::new(Box::new(std::io::Cursor::new(data)), None)
+}
+
+#[test]
+fn merge_test() {
+    let data = std::str::from_utf8(include_bytes!("../assets/journal.export.boots.example")).unwrap();
+    let entries: Vec<&[u8]> = data
+        .split("\n\n")
+        .filter(|entry| !entry.is_empty())
+        .map(str::as_bytes)
+        .collect();
+    assert_eq!(entries.len(), 7);
+
+    let seqnums = |readers: Vec<JournalBackupReader>| -> Vec<u64> {
+        MergedReader::new(readers)
+            .map(|msg| msg.cursor().unwrap().seqnum.unwrap())
+            .collect()
+    };
+
+    // interleaved inputs, sharing the entry with seqnum 4
+    let a = example_reader(&[entries[0], entries[2], entries[3], entries[5]]);
+    let b = example_reader(&[entries[1], entries[3], entries[4], entries[6]]);
+    assert_eq!(seqnums(vec![a, b]), vec![1, 2, 3, 4, 5, 6, 7]);
+
+    // fully overlapping exports
+    let a = example_reader(&entries);
+    let b = example_reader(&entries[2..5]);
+    assert_eq!(seqnums(vec![b, a]), vec![1, 2, 3, 4, 5, 6, 7]);
+
+    assert_eq!(seqnums(vec![]), Vec::<u64>::new());
+}
+
+#[test]
+fn merge_tie_break_test() {
+    // same realtime from different journals keeps the input order, the same journal is ordered by cursor
+    let a = example_reader(&[b"__CURSOR=s=aa;i=2;t=10\n__REALTIME_TIMESTAMP=16\nMESSAGE=a2"]);
+    let b = example_reader(&[
+        b"__CURSOR=s=aa;i=1;t=10\n__REALTIME_TIMESTAMP=16\nMESSAGE=a1",
+        b"__CURSOR=s=bb;i=1;t=10\n__REALTIME_TIMESTAMP=16\nMESSAGE=b1",
+    ]);
+    let c = example_reader(&[b"__REALTIME_TIMESTAMP=15\nMESSAGE=c1"]);
+
+    let messages: Vec<String> = MergedReader::new(vec![a, b, c])
+        .map(|msg| msg.field(b"MESSAGE").unwrap())
+        .collect();
+    assert_eq!(messages, vec!["c1", "a1", "a2", "b1"]);
+}
+
+#[test]
+fn merge_received_time_test() {
+    // ordered by the time the journal received the entries, not by the time the clients reported
+    let a = example_reader(&[
+        b"__REALTIME_TIMESTAMP=10\n_SOURCE_REALTIME_TIMESTAMP=30\nMESSAGE=a1",
+        b"__REALTIME_TIMESTAMP=20\nMESSAGE=a2",
+    ]);
+    let b = example_reader(&[b"__REALTIME_TIMESTAMP=15\n_SOURCE_REALTIME_TIMESTAMP=5\nMESSAGE=b1"]);
+
+    let messages: Vec<String> = MergedReader::new(vec![a, b])
+        .map(|msg| msg.field(b"MESSAGE").unwrap())
+        .collect();
+    assert_eq!(messages, vec!["a1", "b1", "a2"]);
+}
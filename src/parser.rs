@@ -1,8 +1,6 @@
 use nom::bytes::streaming::take;
-use nom::{error::ErrorKind, Err, IResult};
+use nom::{error::ErrorKind, Err, IResult, Needed};
 
-#[cfg(test)]
-use nom::Needed;
 #[cfg(test)]
 use pretty_assertions::assert_eq;
 
@@ -41,7 +39,7 @@ fn parse_value(s: &[u8]) -> IResult<&[u8], &[u8]> {
     match s[0] {
         EQUALS => parse_value_string(s),
         NEWLINE => parse_value_binary(s),
-        _ => Err(Err::Error((&b""[..], ErrorKind::Tag))),
+        _ => Err(Err::Error((s, ErrorKind::Tag))),
     }
     //alt((parse_value_binary, parse_value_string))(s)
 }
@@ -92,7 +90,7 @@ fn parse_value_test() {
 use nom::combinator::opt;
 use nom::sequence::pair;
 
-fn parse_key_value(s: &[u8]) -> IResult<&[u8], KVP> {
+fn parse_key_value(s: &[u8]) -> IResult<&[u8], Kvp<'_>> {
     pair(parse_key, parse_value)(s)
 }
 
@@ -112,9 +110,12 @@ fn parse_key_value_test() {
     );
 }
 
-type KVP <'a> = (&'a [u8], &'a [u8]);
+type Kvp<'a> = (&'a [u8], &'a [u8]);
+
+// Entries that aren't terminated within this many bytes are treated as corrupt data
+const MAX_ENTRY_SIZE: usize = 10_000_000;
 
-fn parse_end_of_msg(s: &[u8]) -> IResult<&[u8], Option<KVP>> {
+fn parse_end_of_msg(s: &[u8]) -> IResult<&[u8], Option<Kvp<'_>>> {
     let newline: [u8; 1] = [NEWLINE];
 
     // if the character we're reading is a newline, it means we're at a message separator, so we return none
@@ -133,10 +134,8 @@ fn parse_end_of_msg(s: &[u8]) -> IResult<&[u8], Option<KVP>> {
         }
     };
 
-    match parse_key_value(s) {
-        Ok((input, res)) => Ok((input, Some(res))),
-        Err(e) => Err(e),
-    }
+    let (input, res) = parse_key_value(s)?;
+    Ok((input, Some(res)))
 }
 
 #[test]
@@ -150,60 +149,121 @@ fn parse_end_of_message_test() {
     assert_eq!(parse_end_of_msg(b"\nu"), Ok((&b"u"[..], None)));
 }
 
+use crate::boot::{Boot, BootSpec};
+use crate::cursor::Cursor;
+use crate::error::{JournalError, JournalErrorKind};
+use crate::matcher::Matches;
+use crate::output::{self, OutputOptions};
+use crate::zone::{Date, Zone};
+use bzip2::read::MultiBzDecoder;
+#[cfg(test)]
 use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::time::Duration;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct JournalMessage {
     fields: Vec<(Vec<u8>, Vec<u8>)>,
 }
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+
+/// Timestamps of an entry in microseconds, realtime since the epoch and monotonic since the boot
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timestamp {
+    /// When the journal received the entry, __REALTIME_TIMESTAMP
+    pub realtime: Option<u64>,
+    /// When the entry was logged according to the client, _SOURCE_REALTIME_TIMESTAMP
+    pub source_realtime: Option<u64>,
+    /// __MONOTONIC_TIMESTAMP
+    pub monotonic: Option<u64>,
+    /// _SOURCE_MONOTONIC_TIMESTAMP, set on kernel messages
+    pub source_monotonic: Option<u64>,
+}
+
+impl Timestamp {
+    /// Wallclock time shown for the entry, preferring the time reported by the source over the time the journal
+    /// received it, the same way journalctl does
+    pub fn realtime_usec(&self) -> Option<u64> {
+        self.source_realtime.or(self.realtime)
+    }
+
+    /// Time since boot shown by short_monotonic, preferring the time reported by the source as well
+    pub fn monotonic_usec(&self) -> Option<u64> {
+        self.source_monotonic.or(self.monotonic)
+    }
+
+    /// Time the journal received the entry, which is what orders the entries of a journal. Falls back to the time
+    /// reported by the source for entries without one.
+    pub fn journal_realtime_usec(&self) -> Option<u64> {
+        self.realtime.or(self.source_realtime)
+    }
+
+    /// The time the journal received the entry as a date, which --since and --until compare like journalctl does.
+    /// None when the entry doesn't have a time or it is out of range.
+    pub fn date_time(&self) -> Option<DateTime<Utc>> {
+        let usec = self.journal_realtime_usec()?;
+
+        // convert from microseconds to seconds and nanoseconds for date lib
+        let secs = usec / 1_000_000;
+        let nanos = (usec % 1_000_000) * 1_000;
+
+        DateTime::<Utc>::from_timestamp(secs as i64, nanos as u32)
+    }
+}
 
 // Well known fields: https://www.freedesktop.org/software/systemd/man/systemd.journal-fields.html
-impl<'a> JournalMessage {
+impl JournalMessage {
+    /// Create a message from its fields in order, field names can be repeated
+    pub fn from_fields(fields: Vec<(Vec<u8>, Vec<u8>)>) -> JournalMessage {
+        JournalMessage { fields }
+    }
+
+    /// Render the message in the journalctl output mode selected by the options, defaulting to short
+    pub fn format(&self, options: &OutputOptions) -> Vec<u8> {
+        output::format(self, options)
+    }
+
     pub fn to_string(&self, mode: Option<OutputMode>) -> String {
-        match mode {
-            None => format!(
-                "{} {} {}[{}]: {}\n",
-                self.timestamp().unwrap_or_else(|| "".to_owned()),
-                self.hostname(),
-                self.comm(),
-                self.pid(),
-                self.message().unwrap_or_else(|| "".to_owned()),
-            ),
-            Some(mode) => match mode {
-                OutputMode::short_iso => format!(
-                    "{} {} {}[{}]: {}\n",
-                    self.timestamp().unwrap_or_else(|| "".to_owned()),
-                    self.hostname(),
-                    self.comm(),
-                    self.pid(),
-                    self.message().unwrap_or_else(|| "".to_owned()),
-                ),
-                _ => panic!("output mode '{}' not implemented", mode),
-            },
-        }
+        let options = OutputOptions {
+            output_mode: mode,
+            ..OutputOptions::default()
+        };
+        String::from_utf8_lossy(&self.format(&options)).into_owned()
     }
 
+    /// The MESSAGE of the entry, invalid UTF-8 sequences are replaced by U+FFFD
     pub fn message(&self) -> Option<String> {
         let key = b"MESSAGE";
-        match self.field(key) {
+        match self.field_lossy(key) {
             Some(s) => {
                 // Sometimes message might be empty, if it is
                 // try and return SYSLOG_RAW instead
                 if s.is_empty() {
                     let syslog_raw = b"SYSLOG_RAW";
-                    self.field(syslog_raw)
+                    self.field_lossy(syslog_raw).map(|s| s.into_owned())
                 } else {
-                    Some(s)
+                    Some(s.into_owned())
                 }
             }
             None => None,
         }
     }
 
+    /// Syslog priority of the entry, 0 (emerg) to 7 (debug)
+    pub fn priority(&self) -> Option<u8> {
+        let key = b"PRIORITY";
+        match self.field_bytes(key) {
+            Some([c @ b'0'..=b'7']) => Some(c - b'0'),
+            _ => None,
+        }
+    }
+
     pub fn pid(&self) -> String {
         let key = b"_PID";
         self.field(key).unwrap_or_else(|| "".to_owned())
@@ -273,52 +333,71 @@ impl<'a> JournalMessage {
     }
     */
 
-    pub fn timestamp(&self) -> Option<String> {
-        if let Some(date) = self.date_time() {
-            return Some(date.format("%+").to_string())
+    /// The realtime and monotonic timestamps of the entry
+    pub fn timestamp(&self) -> Timestamp {
+        Timestamp {
+            realtime: self.usec_field(b"__REALTIME_TIMESTAMP"),
+            source_realtime: self.usec_field(b"_SOURCE_REALTIME_TIMESTAMP"),
+            monotonic: self.usec_field(b"__MONOTONIC_TIMESTAMP"),
+            source_monotonic: self.usec_field(b"_SOURCE_MONOTONIC_TIMESTAMP"),
         }
-
-        None
     }
 
-    fn date_time(&self) -> Option<DateTime<Utc>> {
-        let key = b"_SOURCE_REALTIME_TIMESTAMP";
-        let key2 = b"__REALTIME_TIMESTAMP";
-        let s = match self.field(key) {
-            Some(s) => s,
-            None => match self.field(key2) {
-                Some(s) => s,
-                None => return None,
-            },
-        };
+    // Timestamps that aren't numbers are treated like missing ones
+    fn usec_field(&self, key: &[u8]) -> Option<u64> {
+        std::str::from_utf8(self.field_bytes(key)?).ok()?.parse().ok()
+    }
 
-        //eprintln!("timestamp: {}", s);
-        let micros = match s.parse::<i64>() {
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("Error parsing string to i64 {}: {:?}", s, e);
-                return None;
+    /// Position of the entry, taken from __CURSOR or, for entries without one, from the timestamp fields
+    pub fn cursor(&self) -> Option<Cursor> {
+        if let Some(cursor) = self.field_bytes(b"__CURSOR") {
+            if let Ok(cursor) = std::str::from_utf8(cursor).unwrap_or_default().parse() {
+                return Some(cursor);
             }
-        };
+        }
 
-        // convert from microseconds to seconds and nanoseconds for date lib
-        let secs = micros / 1_000_000;
-        let nanos = micros - (secs * 1_000_000);
+        let cursor = Cursor {
+            boot_id: self.field(b"_BOOT_ID"),
+            monotonic: self.usec_field(b"__MONOTONIC_TIMESTAMP"),
+            realtime: self.usec_field(b"__REALTIME_TIMESTAMP"),
+            ..Cursor::default()
+        };
 
-        let ts = NaiveDateTime::from_timestamp(secs, nanos as u32);
-        let ts_utc: DateTime<Utc> = DateTime::from_utc(ts, Utc);
+        if cursor == Cursor::default() {
+            return None;
+        }
 
-        Some(ts_utc)
+        Some(cursor)
     }
 
+    /// Value of the first field named key, None when the field is missing or its value isn't valid UTF-8
     pub fn field(&self, key: &[u8]) -> Option<String> {
-        for (k, v) in &self.fields {
-            if Vec::from(key) == *k {
-                return Some(std::str::from_utf8(&v[..]).unwrap().to_owned());
-            }
-        }
+        self.field_bytes(key)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(|v| v.to_owned())
+    }
+
+    /// Value of the first field named key, with invalid UTF-8 sequences replaced by U+FFFD
+    pub fn field_lossy(&self, key: &[u8]) -> Option<Cow<'_, str>> {
+        self.field_bytes(key).map(String::from_utf8_lossy)
+    }
+
+    /// Raw value of the first field named key, binary serialized values can hold any bytes
+    pub fn field_bytes(&self, key: &[u8]) -> Option<&[u8]> {
+        self.fields_named(key).next()
+    }
+
+    /// Values of all the fields named key, the export format allows a field to be repeated within an entry
+    pub fn fields_named<'a: 'k, 'k>(&'a self, key: &'k [u8]) -> impl Iterator<Item = &'a [u8]> + 'k {
+        self.fields
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| &v[..])
+    }
 
-        None
+    /// All fields of the entry in the order they were read
+    pub fn fields(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.fields.iter().map(|(k, v)| (&k[..], &v[..]))
     }
 }
 
@@ -333,7 +412,7 @@ arg_enum! {
                                json, json-pretty, json-sse, json-seq, cat,
                                with-unit)
     */
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     #[allow(non_camel_case_types)]
     pub enum OutputMode {
         short,
@@ -355,48 +434,273 @@ arg_enum! {
 }
 
 #[derive(StructOpt, Debug, Clone)]
+#[structopt(setting = structopt::clap::AppSettings::AllowNegativeNumbers)]
 pub struct Filter {
-    // Show entries starting at the specified cursor
-    //#[structopt(short, long)]
-    //cursor: Option<String>,
+    /// Show entries starting at the specified cursor
+    #[structopt(short, long, conflicts_with_all = &["after-cursor", "cursor-file"])]
+    cursor: Option<Cursor>,
+
+    /// Print the cursor after all the entries
+    #[structopt(long)]
+    pub show_cursor: bool,
+
+    /// Show entries after the specified cursor
+    #[structopt(long, conflicts_with = "cursor-file")]
+    after_cursor: Option<Cursor>,
+
+    /// Show entries after the cursor stored in the file, and store the cursor of the newest entry shown in it
+    #[structopt(long)]
+    pub cursor_file: Option<String>,
 
-    // Print the cursor after all the entries
-    //#[structopt(long)]
-    //show_cursor: bool,
+    /// Show entries from the specified boot, the last boot when no boot is given. Negative offsets count back from the
+    /// last boot, positive offsets from the first boot, and a boot id can be followed by an offset relative to it
+    #[structopt(short, long, require_equals = true)]
+    boot: Option<Option<BootSpec>>,
 
-    // Show entries after the specified cursor
-    //#[structopt(long)]
-    //after_cursor: Option<String>,
+    /// Boot id selected by --boot, resolved against the boots in the input
+    #[structopt(skip)]
+    boot_id: Option<String>,
 
     /// Show logs from the specified unit
     #[structopt(short, long)]
     unit: Option<String>,
 
-    /// Show entries not older than the specified date
-    #[structopt(short = "S", long, parse(try_from_str = parse_rel_time))]
-    since: Option<DateTime<chrono::Local>>,
+    /// FIELD=VALUE match expression, taken from the positional arguments
+    #[structopt(skip)]
+    pub matches: Matches,
+
+    /// Filter output by message priorities or priority ranges (emerg, alert, crit, err, warning, notice, info,
+    /// debug or 0-7), a single priority shows that priority and all higher priorities
+    #[structopt(short, long, parse(try_from_str = parse_priority))]
+    priority: Option<RangeInclusive<u8>>,
+
+    /// Show entries with a MESSAGE matching the regular expression
+    #[structopt(short, long)]
+    grep: Option<String>,
+
+    /// Make --grep case sensitive, or insensitive with --case-sensitive=false. By default the pattern is case
+    /// sensitive only when it contains upper case characters
+    #[structopt(long, require_equals = true)]
+    case_sensitive: Option<Option<bool>>,
+
+    /// The compiled --grep pattern
+    #[structopt(skip)]
+    grep_regex: Option<Regex>,
 
-    /// Show entries not newer than the specified date
-    #[structopt(short = "U", long, parse(try_from_str = parse_rel_time))]
-    until: Option<DateTime<chrono::Local>>,
+    /// Show entries not older than the specified date, in the time zone the timestamps are shown in
+    #[structopt(short = "S", long)]
+    since: Option<Date>,
 
-    /// Number of journal entries to show
+    /// Show entries not newer than the specified date, in the time zone the timestamps are shown in
+    #[structopt(short = "U", long)]
+    until: Option<Date>,
+
+    /// Number of journal entries to show, counting back from the newest entry
     #[structopt(short = "n", long)]
     pub lines: Option<u64>,
+}
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+use regex::bytes::{Regex, RegexBuilder};
 
-    
+const PRIORITY_NAMES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
 
-    // Suppress output of hostname field
-    //#[structopt(long)]
-    //no_hostname: bool,
+fn parse_log_level(s: &str) -> Result<u8, String> {
+    if let Some(level) = PRIORITY_NAMES.iter().position(|name| *name == s) {
+        return Ok(level as u8);
+    }
+
+    match s.parse::<u8>() {
+        Ok(level) if level < 8 => Ok(level),
+        _ => Err(format!("Unknown log level {}", s)),
+    }
 }
 
-use chrono::prelude::*;
-use chrono_english::{parse_date_string, DateResult, Dialect};
+// A single level selects that level and everything more important, FROM..TO selects the levels in between
+fn parse_priority(s: &str) -> Result<RangeInclusive<u8>, String> {
+    match s.find("..") {
+        Some(i) => {
+            let from = parse_log_level(&s[..i])?;
+            let to = parse_log_level(&s[i + 2..])?;
+            Ok(from.min(to)..=from.max(to))
+        }
+        None => Ok(0..=parse_log_level(s)?),
+    }
+}
+
+#[test]
+fn parse_priority_test() {
+    assert_eq!(parse_priority("err"), Ok(0..=3));
+    assert_eq!(parse_priority("3"), Ok(0..=3));
+    assert_eq!(parse_priority("warning..emerg"), Ok(0..=4));
+    assert_eq!(parse_priority("crit..warning"), Ok(2..=4));
+    assert_eq!(parse_priority("info..7"), Ok(6..=7));
+    assert!(parse_priority("8").is_err());
+    assert!(parse_priority("error").is_err());
+    assert!(parse_priority("..err").is_err());
+}
+
+// Smart case, escape sequences like \S or \W are character classes rather than upper case characters
+fn pattern_has_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+impl Filter {
+    /// Resume after the cursor stored in the --cursor-file, a missing or empty file starts from the beginning
+    pub fn load_cursor_file(&mut self) -> Result<(), String> {
+        let path = match &self.cursor_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("failed to read cursor file {}: {}", path, e)),
+        };
+
+        if !contents.trim().is_empty() {
+            let cursor = contents
+                .parse()
+                .map_err(|e| format!("failed to parse cursor file {}: {}", path, e))?;
+            self.after_cursor = Some(cursor);
+        }
+
+        Ok(())
+    }
+
+    /// Compile the --grep pattern, applying the --case-sensitive setting
+    pub fn compile_grep(&mut self) -> Result<(), String> {
+        let pattern = match &self.grep {
+            Some(pattern) => pattern,
+            None => return Ok(()),
+        };
+
+        let case_sensitive = match self.case_sensitive {
+            Some(case_sensitive) => case_sensitive.unwrap_or(true),
+            None => pattern_has_uppercase(pattern),
+        };
+
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(|e| format!("invalid --grep pattern: {}", e))?;
+        self.grep_regex = Some(regex);
+
+        Ok(())
+    }
+
+    /// Read the --since and --until dates in the time zone the timestamps are shown in, they're read in UTC until
+    /// then
+    pub fn parse_dates(&mut self, zone: &Zone) -> Result<(), String> {
+        if let Some(since) = &mut self.since {
+            since.reread(zone)?;
+        }
+        if let Some(until) = &mut self.until {
+            until.reread(zone)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check if --boot was given, selecting a boot requires listing the boots of the input first
+    pub fn boot_requested(&self) -> bool {
+        self.boot.is_some()
+    }
+
+    /// Resolve the --boot selection against the boots found in the input
+    pub fn select_boot(&mut self, boots: &[Boot]) -> Result<(), String> {
+        if let Some(spec) = &self.boot {
+            self.boot_id = Some(spec.clone().unwrap_or_default().resolve(boots)?);
+        }
+
+        Ok(())
+    }
+
+    /// Check if the entry is excluded by the filter, shared by the readers of all the input formats
+    pub(crate) fn filters_out(&self, msg: &JournalMessage) -> bool {
+        let mut should_filter = false;
+
+        if self.cursor.is_some() || self.after_cursor.is_some() {
+            if let Some(position) = msg.cursor() {
+                if let Some(cursor) = &self.cursor {
+                    if position.cmp_position(cursor) == Some(Ordering::Less) {
+                        should_filter = true;
+                    }
+                }
+
+                if let Some(cursor) = &self.after_cursor {
+                    if let Some(Ordering::Less) | Some(Ordering::Equal) =
+                        position.cmp_position(cursor)
+                    {
+                        should_filter = true;
+                    }
+                }
+            }
+        }
+
+        if !self.matches.matches(msg) {
+            should_filter = true;
+        }
+
+        // fields can be repeated in an entry, the filters below hit if any of the values matches
+        if let Some(regex) = &self.grep_regex {
+            if !msg.fields_named(b"MESSAGE").any(|message| regex.is_match(message)) {
+                should_filter = true;
+            }
+        }
+
+        if let Some(priority) = &self.priority {
+            let matches = |value: &[u8]| match value {
+                [c @ b'0'..=b'7'] => priority.contains(&(c - b'0')),
+                _ => false,
+            };
+            if !msg.fields_named(b"PRIORITY").any(matches) {
+                should_filter = true;
+            }
+        }
+
+        if let Some(boot_id) = &self.boot_id {
+            if !msg.fields_named(b"_BOOT_ID").any(|id| id == boot_id.as_bytes()) {
+                should_filter = true;
+            }
+        }
+
+        if let Some(unit) = &self.unit {
+            if !msg.fields_named(b"_SYSTEMD_UNIT").any(|u| u == unit.as_bytes()) {
+                should_filter = true;
+            }
+        }
+
+        if let Some(filter_since) = &self.since {
+            if let Some(time) = msg.timestamp().date_time() {
+                if time < filter_since.time() {
+                    should_filter = true;
+                }
+            }
+        }
+
+        if let Some(filter_until) = &self.until {
+            if let Some(time) = msg.timestamp().date_time() {
+                if time > filter_until.time() {
+                    should_filter = true;
+                }
+            }
+        }
 
-//fn parse_rel_time<T, U>(s: &str) -> Result<(T, U), Box<dyn Error>>
-fn parse_rel_time(s: &str) -> DateResult<DateTime<chrono::Local>> {
-    parse_date_string(s, Local::now(), Dialect::Us)
+        should_filter
+    }
 }
 
 pub struct JournalBackupReader {
@@ -404,6 +708,20 @@ pub struct JournalBackupReader {
     remainder: Vec<u8>,
     remainder_read: usize,
 
+    // fields of an entry that was cut off by the end of the input, kept to resume once more data arrives
+    partial: JournalMessage,
+
+    // position in the input for error reporting, offset counts the bytes dropped from the front of remainder
+    offset: u64,
+    entries: u64,
+    entry_start: u64,
+    failed: bool,
+
+    // skip over invalid data to the next entry instead of failing
+    recover: bool,
+    // wait for the rest of an unfinished entry at the end of the input instead of reporting it as truncated
+    follow: bool,
+
     filter: Option<Filter>,
 }
 
@@ -414,117 +732,103 @@ impl JournalBackupReader {
             filter,
             remainder: Vec::new(),
             remainder_read: 0,
+            partial: JournalMessage { fields: Vec::new() },
+            offset: 0,
+            entries: 0,
+            entry_start: 0,
+            failed: false,
+            recover: false,
+            follow: false,
         }
     }
 
-    pub fn open_file(file: String, filter: Option<Filter>) -> std::io::Result<JournalBackupReader> {
-        let mut file = File::open(file)?;
+    /// Treat the end of the input as the end of the data written so far, an unfinished last entry is kept to be
+    /// completed on the next read instead of being reported as truncated
+    pub fn set_follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
 
-        let mut buffer = [0u8; 2];
+    /// Skip invalid data by resuming at the next entry that starts with a __CURSOR= field, the skipped data is
+    /// reported as a JournalErrorKind::Skipped error. Without recovery the reader stops at invalid data.
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
 
-        file.read_exact(&mut buffer)?;
-        file.seek(std::io::SeekFrom::Start(0))?;
+    pub fn open_file(file: String, filter: Option<Filter>) -> std::io::Result<JournalBackupReader> {
+        JournalBackupReader::open_reader(Box::new(File::open(file)?), filter)
+    }
 
-        if is_gz_magic(&buffer[..]) {
-            Ok(JournalBackupReader::new(
-                Box::new(GzDecoder::new(file)),
-                filter,
-            ))
-        } else {
-            Ok(JournalBackupReader::new(Box::new(file), filter))
-        }
+    pub fn open_stdin(filter: Option<Filter>) -> std::io::Result<JournalBackupReader> {
+        JournalBackupReader::open_reader(Box::new(std::io::stdin()), filter)
+    }
+
+    /// Read the input decompressed when it's compressed, see decompress
+    pub fn open_reader(
+        reader: Box<dyn ::std::io::Read>,
+        filter: Option<Filter>,
+    ) -> std::io::Result<JournalBackupReader> {
+        Ok(JournalBackupReader::new(decompress(reader)?, filter))
     }
 
-    fn read(&mut self) -> Option<usize> {
+    fn read(&mut self) -> Result<usize, JournalError> {
         let new_vec = Vec::from(&self.remainder[self.remainder_read..]);
         self.remainder = new_vec;
+        self.offset += self.remainder_read as u64;
         self.remainder_read = 0;
 
         let mut buffer = [0; 32_768];
-        match self.reader.read(&mut buffer) {
-            Ok(l) => {
-                self.remainder.extend_from_slice(&buffer[..l]);
-                return Some(l);
+        loop {
+            match self.reader.read(&mut buffer) {
+                Ok(l) => {
+                    self.remainder.extend_from_slice(&buffer[..l]);
+                    return Ok(l);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(self.error(0, JournalErrorKind::Io(e))),
             }
-            Err(e) => eprintln!("read error: {:?}", e),
         }
-
-        None
     }
 
-    fn should_filter(&mut self, msg: &JournalMessage) -> bool {
-        match &self.filter {
-            Some(filter) => {
-                let mut should_filter = false;
-
-                if let Some(unit) = &filter.unit {
-                    if *unit != msg.systemd_unit() {
-                        //eprintln!("{:?} != {:?}", unit, msg.systemd_unit().unwrap());
-                        should_filter = true;
-                    }
-                }
-
-                if let Some(filter_since) = &filter.since {
-                    if let Some(time) = msg.date_time() {
-                        if time < *filter_since {
-                            should_filter = true;
-                        }
-                    }
-                }
-
-                if let Some(filter_until) = &filter.until {
-                    if let Some(time) = msg.date_time() {
-                        if time > *filter_until {
-                            should_filter = true;
-                        }
-                    }
-                }
-
-                should_filter
-            }
-            None => false,
+    // Error at pos bytes into the unparsed data
+    fn error(&self, pos: usize, kind: JournalErrorKind) -> JournalError {
+        JournalError {
+            offset: self.offset + (self.remainder_read + pos) as u64,
+            entry: self.entries,
+            kind,
         }
     }
-}
 
-impl Iterator for JournalBackupReader {
-    type Item = JournalMessage;
+    /// Iterate over the entries, yielding the errors instead of ending the iteration
+    pub fn results(self) -> JournalResults {
+        JournalResults(self)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.remainder.is_empty() {
-            match self.read() {
-                Some(l) => {
-                    if let 0 = l {
-                        return None;
-                    }
-                } // EOF return None
-                None => return None,
-            }
+    /// Read the next entry. I/O errors can be retried by calling this again, after invalid data the reader
+    /// doesn't return any more entries unless recovery is enabled.
+    pub fn next_result(&mut self) -> Option<Result<JournalMessage, JournalError>> {
+        if self.failed {
+            return None;
         }
 
-        let mut result = JournalMessage { fields: Vec::new() };
+        let mut result = std::mem::replace(&mut self.partial, JournalMessage { fields: Vec::new() });
 
         // if we've read in more than 10MiB something is probably wrong and we should quit processing
-        while self.remainder.len() < 10_000_000 {
+        while self.remainder.len() < MAX_ENTRY_SIZE {
             let mut more = false;
 
             match parse_end_of_msg(&self.remainder[self.remainder_read..]) {
                 // TODO: no clone
                 Ok((rem, kvp)) => {
-                    //self.remainder = rem.to_vec();
                     self.remainder_read = self.remainder.len() - rem.len();
                     match kvp {
                         Some((key, value)) => {
-                            /*eprintln!(
-                                "kvp: {} = {}",
-                                std::str::from_utf8(key).unwrap(),
-                                std::str::from_utf8(value).unwrap()
-                            );*/
                             result.fields.push((key.to_vec(), value.to_vec()));
                         }
                         None => {
+                            self.entries += 1;
+                            self.entry_start = self.offset + self.remainder_read as u64;
                             if !self.should_filter(&result) {
-                                return Some(result);
+                                return Some(Ok(result));
                             } else {
                                 result = JournalMessage { fields: Vec::new() };
                             }
@@ -532,27 +836,194 @@ impl Iterator for JournalBackupReader {
                     }
                 }
                 Err(e) => match e {
+                    // a binary value can announce a size that is never going to fit in an entry
+                    Err::Incomplete(Needed::Size(size)) if size > MAX_ENTRY_SIZE => {
+                        return Some(self.invalid(0, JournalErrorKind::EntryTooLarge(MAX_ENTRY_SIZE)));
+                    }
                     Err::Incomplete(_) => {
                         more = true;
                     }
-                    Err::Error((_, kind)) => panic!("Unexpected parser error: {:?}", kind),
-                    Err::Failure(e) => panic!("Unexpected parser error: {:?}", e),
+                    Err::Error((input, kind)) | Err::Failure((input, kind)) => {
+                        let pos = self.remainder.len() - self.remainder_read - input.len();
+                        return Some(self.invalid(pos, JournalErrorKind::Parse(describe_error(kind))));
+                    }
                 },
             }
 
             if more {
                 match self.read() {
-                    Some(l) => {
-                        if let 0 = l {
-                            return None;
-                        }
-                    } // EOF return None
-                    None => return None,
+                    Ok(0) => {
+                        self.partial = result;
+                        return self.end_of_input();
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        self.partial = result;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+
+        Some(self.invalid(0, JournalErrorKind::EntryTooLarge(MAX_ENTRY_SIZE)))
+    }
+
+    // At the end of the input an unfinished entry is either still being written when following, or truncated
+    fn end_of_input(&mut self) -> Option<Result<JournalMessage, JournalError>> {
+        let bytes = self.offset + self.remainder.len() as u64 - self.entry_start;
+        if self.follow || bytes == 0 {
+            return None;
+        }
+
+        let kind = match self.recover {
+            true => JournalErrorKind::Skipped { bytes, entries: 1 },
+            false => JournalErrorKind::Truncated { bytes },
+        };
+        let error = JournalError {
+            offset: self.entry_start,
+            entry: self.entries,
+            kind,
+        };
+
+        self.partial = JournalMessage { fields: Vec::new() };
+        self.remainder_read = self.remainder.len();
+        self.entry_start += bytes;
+        self.entries += 1;
+        self.failed = !self.recover;
+
+        Some(Err(error))
+    }
+
+    // Handle invalid data at pos bytes into the unparsed data, either by skipping to the next entry or by failing
+    fn invalid(&mut self, pos: usize, kind: JournalErrorKind) -> Result<JournalMessage, JournalError> {
+        if !self.recover {
+            self.failed = true;
+            return Err(self.error(pos, kind));
+        }
+
+        match self.resync() {
+            Ok(skipped) => Err(skipped),
+            Err(e) => {
+                self.failed = true;
+                Err(e)
+            }
+        }
+    }
+
+    // Drop the entry being read and skip ahead to the next blank line followed by a __CURSOR= field
+    fn resync(&mut self) -> Result<JournalError, JournalError> {
+        const ENTRY_START: &[u8] = b"\n\n__CURSOR=";
+
+        let offset = self.entry_start;
+        let entry = self.entries;
+        let mut separators = 0;
+
+        let at_eof = loop {
+            let data = &self.remainder[self.remainder_read..];
+            if let Some(i) = data.windows(ENTRY_START.len()).position(|w| w == ENTRY_START) {
+                // the blank line before the next entry terminates the last skipped entry
+                separators += count_separators(&data[..i + 2]);
+                self.remainder_read += i + 2;
+                break false;
+            }
+
+            // keep the bytes that could be the start of the next entry
+            let keep = data.len().min(ENTRY_START.len() - 1);
+            separators += count_separators(&data[..(data.len() - keep + 1).min(data.len())]);
+            self.remainder_read += data.len() - keep;
+
+            if self.read()? == 0 {
+                separators += count_separators(&self.remainder[self.remainder_read..]);
+                self.remainder_read = self.remainder.len();
+                break true;
+            }
+        };
+
+        self.entry_start = self.offset + self.remainder_read as u64;
+        let skipped = JournalErrorKind::Skipped {
+            bytes: self.entry_start - offset,
+            // data after the last separator is an entry cut off by the end of the input
+            entries: separators + (at_eof && !self.remainder.ends_with(b"\n\n")) as u64,
+        };
+        self.entries += separators;
+
+        Ok(JournalError {
+            offset,
+            entry,
+            kind: skipped,
+        })
+    }
+
+    fn should_filter(&self, msg: &JournalMessage) -> bool {
+        self.filter.as_ref().is_some_and(|filter| filter.filters_out(msg))
+    }
+}
+
+// Count the entry separators starting in data, the last byte is only included as the start of a separator
+fn count_separators(data: &[u8]) -> u64 {
+    data.windows(2).filter(|w| w == b"\n\n").count() as u64
+}
+
+fn describe_error(kind: ErrorKind) -> String {
+    match kind {
+        ErrorKind::TakeWhile1 => "field name expected".to_owned(),
+        ErrorKind::Tag => "newline expected".to_owned(),
+        kind => kind.description().to_owned(),
+    }
+}
+
+/// Iterating the reader directly ends at the first error and passes over skipped data, use results() to see the
+/// errors
+impl Iterator for JournalBackupReader {
+    type Item = JournalMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_result()? {
+                Ok(msg) => return Some(msg),
+                Err(JournalError { kind: JournalErrorKind::Skipped { .. }, .. }) => (),
+                Err(_) => {
+                    self.failed = true;
+                    return None;
                 }
             }
         }
+    }
+}
+
+pub struct JournalResults(JournalBackupReader);
 
-        panic!("Runaway memory growth in journal parsing")
+impl Iterator for JournalResults {
+    type Item = Result<JournalMessage, JournalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_result()
+    }
+}
+
+/// Follow input that is still being written, like tail -f. When the readers run out of data the iterator waits for
+/// more instead of ending, entries that were only partially written are completed once the rest arrives.
+pub struct Follow<I: Iterator<Item = JournalMessage>> {
+    reader: I,
+    interval: Duration,
+}
+
+impl<I: Iterator<Item = JournalMessage>> Follow<I> {
+    pub fn new(reader: I, interval: Duration) -> Follow<I> {
+        Follow { reader, interval }
+    }
+}
+
+impl<I: Iterator<Item = JournalMessage>> Iterator for Follow<I> {
+    type Item = JournalMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.next() {
+                Some(msg) => return Some(msg),
+                None => std::thread::sleep(self.interval),
+            }
+        }
     }
 }
 
@@ -798,12 +1269,473 @@ fn multiple_message_test() {
     assert_eq!(r2.next(), None);
 }
 
-fn is_gz_magic(s: &[u8]) -> bool {
-    fn gz_magic(s: &[u8]) -> IResult<&[u8], &[u8]> {
-        let gz_magic: &[u8] = &[0x1f, 0x8b];
-        tag(gz_magic)(s)
+#[cfg(test)]
+struct OneByteReader(&'static [u8]);
+
+#[cfg(test)]
+impl Read for OneByteReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok(1)
+    }
+}
+
+// Reader over data that is still being written, only the first available bytes can be read
+#[cfg(test)]
+struct GrowingReader(&'static [u8], std::rc::Rc<std::cell::Cell<usize>>);
+
+#[cfg(test)]
+impl Read for GrowingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.1.get().min(self.0.len());
+        let n = buf.len().min(available);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        self.1.set(available - n);
+        Ok(n)
+    }
+}
+
+#[test]
+fn resume_test() {
+    let data = include_bytes!("../assets/journal.binary.example");
+    let expected: Vec<JournalMessage> = JournalBackupReader::new(Box::new(&data[..]), None).collect();
+
+    // hand over the data in chunks that cut through fields, binary sizes and entry separators
+    for chunk in &[1, 7, 100, 301] {
+        let available = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut reader = JournalBackupReader::new(Box::new(GrowingReader(data, available.clone())), None);
+        reader.set_follow(true);
+
+        let mut messages = Vec::new();
+        for _ in 0..=data.len() / chunk {
+            available.set(available.get() + chunk);
+            messages.extend(reader.by_ref());
+        }
+        assert_eq!(messages, expected);
+    }
+}
+
+// Reader failing once with the error before handing over the data
+#[cfg(test)]
+struct FailingReader(&'static [u8], Option<std::io::ErrorKind>);
+
+#[cfg(test)]
+impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.1.take() {
+            Some(kind) => Err(std::io::Error::new(kind, "failing reader")),
+            None => self.0.read(buf),
+        }
+    }
+}
+
+#[test]
+fn results_test() {
+    let data = b"MESSAGE=one\n\nMESSAGE=two\n\n=bad\n\nMESSAGE=three\n\n";
+    let mut results = JournalBackupReader::new(Box::new(&data[..]), None).results();
+
+    assert_eq!(results.next().unwrap().unwrap().message(), Some("one".to_owned()));
+    assert_eq!(results.next().unwrap().unwrap().message(), Some("two".to_owned()));
+    let e = results.next().unwrap().unwrap_err();
+    assert_eq!((e.offset, e.entry), (26, 2));
+    assert!(matches!(e.kind, JournalErrorKind::Parse(_)));
+    assert!(results.next().is_none());
+
+    // iterating the reader directly stops at the error
+    assert_eq!(JournalBackupReader::new(Box::new(&data[..]), None).count(), 2);
+
+    // a binary size that can't fit is reported without reading on
+    let data = b"MESSAGE=one\n\nMESSAGE\n\xff\xff\xff\xff\x00\x00\x00\x00";
+    let mut results = JournalBackupReader::new(Box::new(&data[..]), None).results();
+    assert!(results.next().unwrap().is_ok());
+    let e = results.next().unwrap().unwrap_err();
+    assert_eq!((e.offset, e.entry), (13, 1));
+    assert!(matches!(e.kind, JournalErrorKind::EntryTooLarge(_)));
+
+    // I/O errors can be retried, interrupted reads are retried by the reader
+    let data = b"MESSAGE=one\n\n";
+    let mut reader = JournalBackupReader::new(Box::new(FailingReader(data, Some(std::io::ErrorKind::Other))), None);
+    assert!(matches!(reader.next_result(), Some(Err(JournalError { kind: JournalErrorKind::Io(_), .. }))));
+    assert!(reader.next_result().unwrap().is_ok());
+
+    let reader = FailingReader(data, Some(std::io::ErrorKind::Interrupted));
+    assert_eq!(JournalBackupReader::new(Box::new(reader), None).count(), 1);
+}
+
+#[test]
+fn recover_test() {
+    let data = std::str::from_utf8(include_bytes!("../assets/journal.export.boots.example")).unwrap();
+    let entries: Vec<&str> = data.split_terminator("\n\n").collect();
+    let read = |data: String, recover: bool| -> Vec<Result<u64, (u64, u64, String)>> {
+        let mut reader = JournalBackupReader::new(Box::new(std::io::Cursor::new(data)), None);
+        reader.set_recover(recover);
+        reader
+            .results()
+            .map(|result| match result {
+                Ok(msg) => Ok(msg.cursor().unwrap().seqnum.unwrap()),
+                Err(e) => Err((e.offset, e.entry, e.to_string())),
+            })
+            .collect()
+    };
+
+    // a corrupt field in the third entry and a spliced in entry without a cursor, up to the fifth entry
+    let mut corrupt = entries.clone();
+    corrupt[2] = "__CURSOR=s=a1b2\n=garbage";
+    corrupt.insert(3, "MESSAGE=no cursor");
+    let corrupt = corrupt.join("\n\n") + "\n\n";
+    let offset = (entries[0].len() + entries[1].len() + 4) as u64;
+    let skipped = "skipped 45 bytes and 2 entries of invalid data at byte";
+
+    assert_eq!(
+        read(corrupt.clone(), true),
+        vec![
+            Ok(1),
+            Ok(2),
+            Err((offset, 2, format!("{} {} in entry 2", skipped, offset))),
+            Ok(4),
+            Ok(5),
+            Ok(6),
+            Ok(7)
+        ]
+    );
+    assert_eq!(read(corrupt.clone(), false).len(), 3);
+    assert_eq!(JournalBackupReader::new(Box::new(std::io::Cursor::new(corrupt.clone())), None).count(), 2);
+
+    // garbage at the end of the input
+    let garbage = entries[..2].join("\n\n") + "\n\n=junk\n\nmore=1\n\n";
+    let message = format!("skipped 15 bytes and 2 entries of invalid data at byte {} in entry 2", offset);
+    assert_eq!(read(garbage, true), vec![Ok(1), Ok(2), Err((offset, 2, message))]);
+
+    // truncated last entry
+    let truncated = entries[..2].join("\n\n") + "\n\n" + &entries[2][..50];
+    let message = format!("skipped 50 bytes and 1 entry of invalid data at byte {} in entry 2", offset);
+    assert_eq!(read(truncated.clone(), true), vec![Ok(1), Ok(2), Err((offset, 2, message))]);
+    let message = format!("truncated entry of 50 bytes at byte {} in entry 2", offset);
+    assert_eq!(read(truncated, false), vec![Ok(1), Ok(2), Err((offset, 2, message))]);
+
+    // the plain iterator passes over the skipped data
+    let mut reader = JournalBackupReader::new(Box::new(std::io::Cursor::new(corrupt)), None);
+    reader.set_recover(true);
+    assert_eq!(reader.count(), 6);
+}
+
+#[test]
+fn open_reader_test() {
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let compressed = include_bytes!("../assets/journal.export.3.example.gz");
+
+    // pipes can hand over the magic bytes one at a time
+    let plain: Vec<JournalMessage> =
+        JournalBackupReader::open_reader(Box::new(OneByteReader(data)), None).unwrap().collect();
+    let gz: Vec<JournalMessage> =
+        JournalBackupReader::open_reader(Box::new(OneByteReader(compressed)), None).unwrap().collect();
+
+    assert_eq!(plain.len(), 3);
+    assert_eq!(plain, gz);
+
+    // the other formats were written by the xz, zstd, bzip2 and lz4 command line tools
+    for compressed in &[
+        &compressed[..],
+        &include_bytes!("../assets/journal.export.3.example.xz")[..],
+        &include_bytes!("../assets/journal.export.3.example.zst")[..],
+        &include_bytes!("../assets/journal.export.3.example.bz2")[..],
+        &include_bytes!("../assets/journal.export.3.example.lz4")[..],
+    ] {
+        let messages: Vec<JournalMessage> =
+            JournalBackupReader::open_reader(Box::new(OneByteReader(compressed)), None).unwrap().collect();
+        assert_eq!(messages, plain);
+
+        // concatenated streams, like multi-member gzip files, are read to the end
+        let concatenated = [*compressed, *compressed].concat();
+        let messages: Vec<JournalMessage> =
+            JournalBackupReader::open_reader(Box::new(std::io::Cursor::new(concatenated)), None).unwrap().collect();
+        assert_eq!(messages, [&plain[..], &plain[..]].concat());
+    }
+
+    // input shorter than the magic
+    assert_eq!(JournalBackupReader::open_reader(Box::new(&b""[..]), None).unwrap().next(), None);
+    assert_eq!(JournalBackupReader::open_reader(Box::new(&b"\x1f"[..]), None).unwrap().next(), None);
+}
+
+#[test]
+fn cursor_filter_test() {
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let seqnums = |args: &[&str]| -> Vec<Option<u64>> {
+        let filter = Filter::from_iter(args);
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter))
+            .map(|msg| msg.cursor().unwrap().seqnum)
+            .collect()
+    };
+
+    let second = "s=4d4c07169cf346bf84c0682dee9f876d;i=f7102;b=29afc66917be48d58ba2a628b946422c;m=a253133c;t=5ae0622d0fbdd;x=334ef41b13d414a9";
+    assert_eq!(seqnums(&["jrnlb", "--cursor", second]), vec![Some(0xf7102), Some(0xf7103)]);
+    assert_eq!(seqnums(&["jrnlb", "--after-cursor", second]), vec![Some(0xf7103)]);
+
+    // a cursor from another journal file falls back to comparing the realtime timestamp
+    assert_eq!(seqnums(&["jrnlb", "--after-cursor", "s=other;i=1;t=5ae0622d0fbb8"]), vec![Some(0xf7102), Some(0xf7103)]);
+
+    assert!(Filter::from_iter_safe(&["jrnlb", "--cursor", second, "--after-cursor", second]).is_err());
+}
+
+#[test]
+fn priority_filter_test() {
+    let data = include_bytes!("../assets/journal.export.modes.example");
+    let messages = |args: &[&str]| -> Vec<String> {
+        let filter = Filter::from_iter(args);
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter))
+            .map(|msg| msg.field(b"MESSAGE").unwrap())
+            .collect()
+    };
+
+    // entries without a PRIORITY are never shown when filtering by priority
+    assert_eq!(messages(&["jrnlb", "-p", "err"]), vec!["no identifier here"]);
+    assert_eq!(messages(&["jrnlb", "-p", "4"]), vec!["no identifier here", "first line\nsecond line\nthird"]);
+    assert_eq!(messages(&["jrnlb", "--priority", "info..warning"]), vec!["hello world", "first line\nsecond line\nthird"]);
+    assert!(Filter::from_iter_safe(&["jrnlb", "-p", "loud"]).is_err());
+}
+
+#[test]
+fn field_access_test() {
+    let data = b"MESSAGE\n\x05\x00\x00\x00\x00\x00\x00\x00a\xffb\nc\nTAG=one\nTAG=two\nEMPTY=\n\n";
+    let msg = JournalBackupReader::new(Box::new(&data[..]), None).next().unwrap();
+
+    assert_eq!(msg.field_bytes(b"MESSAGE"), Some(&b"a\xffb\nc"[..]));
+    assert_eq!(msg.field(b"MESSAGE"), None);
+    assert_eq!(msg.field_lossy(b"MESSAGE").unwrap(), "a\u{fffd}b\nc");
+    assert_eq!(msg.message(), Some("a\u{fffd}b\nc".to_owned()));
+
+    assert_eq!(msg.field(b"TAG"), Some("one".to_owned()));
+    assert_eq!(msg.fields_named(b"TAG").collect::<Vec<_>>(), vec![&b"one"[..], &b"two"[..]]);
+    assert_eq!(msg.fields_named(b"MISSING").count(), 0);
+    assert_eq!(msg.field(b"EMPTY"), Some("".to_owned()));
+}
+
+#[test]
+fn repeated_fields_filter_test() {
+    let data = b"MESSAGE=first\nMESSAGE=second\nPRIORITY=6\nPRIORITY=3\n_SYSTEMD_UNIT=a.service\n_SYSTEMD_UNIT=b.service\n\n";
+    let count = |args: &[&str]| -> usize {
+        let mut filter = Filter::from_iter(args);
+        filter.compile_grep().unwrap();
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter)).count()
+    };
+
+    // a filter hits if any of the values matches
+    assert_eq!(count(&["jrnlb", "-g", "^second$"]), 1);
+    assert_eq!(count(&["jrnlb", "-p", "err"]), 1);
+    assert_eq!(count(&["jrnlb", "-u", "b.service"]), 1);
+    assert_eq!(count(&["jrnlb", "-g", "third"]), 0);
+    assert_eq!(count(&["jrnlb", "-p", "crit"]), 0);
+}
+
+#[test]
+fn grep_filter_test() {
+    let data = include_bytes!("../assets/journal.export.modes.example");
+    let messages = |args: &[&str]| -> Vec<String> {
+        let mut filter = Filter::from_iter(args);
+        filter.compile_grep().unwrap();
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter))
+            .map(|msg| msg.field(b"MESSAGE").unwrap())
+            .collect()
+    };
+
+    assert_eq!(messages(&["jrnlb", "-g", "hello"]), vec!["hello world"]);
+    // lower case patterns are case insensitive unless asked otherwise
+    assert_eq!(messages(&["jrnlb", "-g", "HÉLLO|^No"]), Vec::<String>::new());
+    assert_eq!(messages(&["jrnlb", "-g", "héllo|^no"]), vec!["no identifier here", "tab\there and unicode héllo ✓"]);
+    assert_eq!(messages(&["jrnlb", "-g", "^NO", "--case-sensitive=false"]), vec!["no identifier here"]);
+    assert_eq!(messages(&["jrnlb", "-g", "^no", "--case-sensitive"]), vec!["no identifier here"]);
+    assert_eq!(messages(&["jrnlb", "--grep", "\\Sird$"]), vec!["first line\nsecond line\nthird"]);
+
+    // binary serialized messages are matched on their full value
+    let data = include_bytes!("../assets/journal.binary.example");
+    let mut filter = Filter::from_iter(&["jrnlb", "-g", "^foo\nbar$"]);
+    filter.compile_grep().unwrap();
+    assert_eq!(JournalBackupReader::new(Box::new(&data[..]), Some(filter)).count(), 1);
+
+    assert!(Filter::from_iter(&["jrnlb", "-g", "("]).compile_grep().is_err());
+}
+
+/// Detect compressed input by peeking at the magic bytes, the peeked bytes are chained back in front of the reader
+/// so this works on pipes and other readers that can't seek. Concatenated compressed streams, like multi-member gzip
+/// files, are read as one. Input that isn't compressed is passed through.
+pub fn decompress<'a>(mut reader: Box<dyn Read + 'a>) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    reader.by_ref().take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+
+    let compression = detect_compression(&magic[..]);
+    let reader = Box::new(std::io::Cursor::new(magic).chain(reader));
+
+    Ok(match compression {
+        None => reader,
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(reader)),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        Some(Compression::Bzip2) => Box::new(MultiBzDecoder::new(reader)),
+        Some(Compression::Lz4) => Box::new(MultiLz4Decoder::new(reader)),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    Lz4,
+}
+
+// The lz4 frame decoder ends at the end of the first frame, frames following it are decoded by a new decoder like
+// the lz4 tool does
+struct MultiLz4Decoder<R: Read> {
+    decoder: Option<lz4_flex::frame::FrameDecoder<BufReader<R>>>,
+}
+
+impl<R: Read> MultiLz4Decoder<R> {
+    fn new(reader: R) -> MultiLz4Decoder<R> {
+        MultiLz4Decoder {
+            decoder: Some(lz4_flex::frame::FrameDecoder::new(BufReader::new(reader))),
+        }
+    }
+}
+
+impl<R: Read> Read for MultiLz4Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let decoder = match &mut self.decoder {
+                Some(decoder) => decoder,
+                None => return Ok(0),
+            };
+
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            let mut reader = self.decoder.take().unwrap().into_inner();
+            if !reader.fill_buf()?.is_empty() {
+                self.decoder = Some(lz4_flex::frame::FrameDecoder::new(reader));
+            }
+        }
     }
+}
 
-    matches!(gz_magic(s), Ok(_))
+// Longest of the magic numbers below
+const MAGIC_LEN: usize = 6;
+
+fn detect_compression(s: &[u8]) -> Option<Compression> {
+    const MAGIC: &[(&[u8], Compression)] = &[
+        (&[0x1f, 0x8b], Compression::Gzip),
+        (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Compression::Xz),
+        (&[0x28, 0xb5, 0x2f, 0xfd], Compression::Zstd),
+        (b"BZh", Compression::Bzip2),
+        (&[0x04, 0x22, 0x4d, 0x18], Compression::Lz4),
+    ];
+
+    MAGIC
+        .iter()
+        .find(|(magic, _)| s.starts_with(magic))
+        .map(|(_, compression)| *compression)
 }
 
+#[test]
+fn since_until_zone_test() {
+    // the entries of the example were logged between 15:51:00.70 and 15:51:00.74 UTC
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let count = |args: &[&str], zone: &str| -> usize {
+        let mut filter = Filter::from_iter(args);
+        filter.parse_dates(&zone.parse().unwrap()).unwrap();
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter)).count()
+    };
+
+    assert_eq!(count(&["jrnlb", "--since", "2020-08-29 15:51:00"], "UTC"), 3);
+    assert_eq!(count(&["jrnlb", "--until", "2020-08-29 15:51:00"], "UTC"), 0);
+    assert_eq!(count(&["jrnlb", "--since", "2020-08-29 17:51:01"], "Europe/Berlin"), 0);
+    assert_eq!(count(&["jrnlb", "--until", "2020-08-29 17:51:01"], "Europe/Berlin"), 3);
+
+    // the time the journal received the entry is compared, not the time the client logged it at
+    let data = b"__REALTIME_TIMESTAMP=1598716261000000\n_SOURCE_REALTIME_TIMESTAMP=1598716259000000\nMESSAGE=late\n\n\
+        __REALTIME_TIMESTAMP=18446744073709551615\nMESSAGE=corrupt\n\n";
+    let count = |args: &[&str]| -> usize {
+        let filter = Filter::from_iter(args);
+        JournalBackupReader::new(Box::new(&data[..]), Some(filter)).count()
+    };
+
+    // without parse_dates the dates are read in UTC
+    assert_eq!(count(&["jrnlb", "--since", "2020-08-29 15:51:00"]), 2);
+    assert_eq!(count(&["jrnlb", "--until", "2020-08-29 15:51:00"]), 1);
+
+    assert!(Filter::from_iter_safe(&["jrnlb", "--since", "someday"]).is_err());
+}
+
+#[test]
+fn timestamp_test() {
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let timestamps: Vec<Timestamp> = JournalBackupReader::new(Box::new(&data[..]), None)
+        .map(|msg| msg.timestamp())
+        .collect();
+
+    assert_eq!(
+        timestamps,
+        vec![
+            Timestamp {
+                realtime: Some(1598716260711352),
+                source_realtime: Some(1598716260706706),
+                monotonic: Some(2723353367),
+                source_monotonic: None,
+            },
+            Timestamp {
+                realtime: Some(1598716260711389),
+                source_realtime: Some(1598716260706709),
+                monotonic: Some(2723353404),
+                source_monotonic: None,
+            },
+            Timestamp {
+                realtime: Some(1598716260738150),
+                source_realtime: Some(1598716260738112),
+                monotonic: Some(2723380166),
+                source_monotonic: None,
+            },
+        ]
+    );
+
+    assert_eq!(timestamps[0].realtime_usec(), Some(1598716260706706));
+    assert_eq!(timestamps[0].journal_realtime_usec(), Some(1598716260711352));
+    assert_eq!(timestamps[0].monotonic_usec(), Some(2723353367));
+    assert_eq!(
+        timestamps[0].date_time().unwrap().to_rfc3339(),
+        "2020-08-29T15:51:00.711352+00:00"
+    );
+
+    let kernel = Timestamp {
+        monotonic: Some(2723353367),
+        source_monotonic: Some(1500000),
+        ..Timestamp::default()
+    };
+    assert_eq!(kernel.monotonic_usec(), Some(1500000));
+    assert_eq!(kernel.realtime_usec(), None);
+    assert_eq!(kernel.date_time(), None);
+
+    let corrupt = Timestamp {
+        realtime: Some(u64::MAX),
+        ..Timestamp::default()
+    };
+    assert_eq!(corrupt.date_time(), None);
+
+    let invalid = JournalMessage::from_fields(vec![
+        (b"__REALTIME_TIMESTAMP".to_vec(), b"yesterday".to_vec()),
+        (b"_SOURCE_REALTIME_TIMESTAMP".to_vec(), b"1598716260706706".to_vec()),
+    ]);
+    assert_eq!(invalid.timestamp().realtime, None);
+    assert_eq!(invalid.timestamp().journal_realtime_usec(), Some(1598716260706706));
+
+    let lines: Vec<String> = JournalBackupReader::new(Box::new(&data[..]), None)
+        .map(|msg| msg.to_string(Some(OutputMode::short_monotonic)))
+        .collect();
+    assert!(lines[0].starts_with("[ 2723.353367] knisbet-dev rsyslogd[654]: "));
+    assert!(lines[2].starts_with("[ 2723.380166] knisbet-dev systemd-resolved[590]: "));
+}
@@ -0,0 +1,182 @@
+use crate::output::{escape_control, format_bytes, format_realtime, utf8_is_printable, OutputOptions};
+use crate::parser::{JournalMessage, OutputMode};
+use std::str::FromStr;
+
+#[cfg(test)]
+use crate::parser::JournalBackupReader;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* output templates
+--template prints each entry with a format string, for line formats none of the output modes provide:
+- {FIELD} is replaced by the first value of the field, or nothing when the entry doesn't have it
+- {timestamp} is the time of the entry, formatted like the short mode given by --output
+- values that aren't printable are replaced by "[<size> blob data]", or shown with their control characters
+  escaped with --all, as in the short modes
+- {{ and }} are literal braces
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(String),
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Write the entry with the template, followed by a newline
+    pub fn render(&self, msg: &JournalMessage, options: &OutputOptions, out: &mut Vec<u8>) {
+        let mode = options.output_mode.unwrap_or(OutputMode::short);
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.extend_from_slice(text.as_bytes()),
+                Part::Timestamp => {
+                    let timestamp = msg.timestamp().realtime_usec();
+                    if let Some(ts) = timestamp.and_then(|usec| format_realtime(usec, mode, &options.zone)) {
+                        out.extend_from_slice(ts.as_bytes());
+                    }
+                }
+                Part::Field(name) => match msg.field_bytes(name.as_bytes()) {
+                    Some(value) if utf8_is_printable(value, true) => out.extend_from_slice(value),
+                    Some(value) if options.all => out.extend_from_slice(&escape_control(value)),
+                    Some(value) => out.extend_from_slice(format!("[{} blob data]", format_bytes(value.len())).as_bytes()),
+                    None => (),
+                },
+            }
+        }
+
+        out.push(b'\n');
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(format!("unterminated field in template '{}'", s)),
+                            Some(c) => name.push(c),
+                        }
+                    }
+                    if name.is_empty() {
+                        return Err(format!("empty field in template '{}'", s));
+                    }
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(match name.as_str() {
+                        "timestamp" => Part::Timestamp,
+                        _ => Part::Field(name),
+                    });
+                }
+                '}' => return Err(format!("unmatched '}}' in template '{}', use '}}}}' for a literal brace", s)),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+#[test]
+fn parse_template_test() {
+    let template: Template = "{timestamp} {_HOSTNAME} {{{MESSAGE}}}".parse().unwrap();
+    assert_eq!(
+        template.parts,
+        vec![
+            Part::Timestamp,
+            Part::Text(" ".to_owned()),
+            Part::Field("_HOSTNAME".to_owned()),
+            Part::Text(" {".to_owned()),
+            Part::Field("MESSAGE".to_owned()),
+            Part::Text("}".to_owned()),
+        ]
+    );
+
+    assert_eq!("".parse::<Template>().unwrap().parts, vec![]);
+    assert!("{MESSAGE".parse::<Template>().is_err());
+    assert!("{MES{SAGE}".parse::<Template>().is_err());
+    assert!("{}".parse::<Template>().is_err());
+    assert!("MESSAGE}".parse::<Template>().is_err());
+}
+
+#[test]
+fn render_template_test() {
+    let data = include_bytes!("../assets/journal.export.3.example");
+    let msg = JournalBackupReader::new(Box::new(&data[..]), None).next().unwrap();
+    let render = |msg: &JournalMessage, template: &str, options: OutputOptions| -> Vec<u8> {
+        let mut out = Vec::new();
+        template.parse::<Template>().unwrap().render(msg, &options, &mut out);
+        out
+    };
+    let mode = |mode: OutputMode| OutputOptions {
+        output_mode: Some(mode),
+        ..OutputOptions::default()
+    };
+
+    assert_eq!(
+        render(&msg, "{timestamp} {_HOSTNAME} {SYSLOG_IDENTIFIER}[{_PID}] {MISSING}|", OutputOptions::default()),
+        b"Aug 29 15:51:00 knisbet-dev rsyslogd[654] |\n".to_vec()
+    );
+    // the time the entry was logged at, as in the short modes
+    assert_eq!(
+        render(&msg, "{timestamp} {__REALTIME_TIMESTAMP}", mode(OutputMode::short_unix)),
+        b"1598716260.706706 1598716260711352\n".to_vec()
+    );
+    assert_eq!(
+        render(
+            &msg,
+            "{timestamp}",
+            OutputOptions {
+                zone: "Europe/Berlin".parse().unwrap(),
+                ..mode(OutputMode::short_iso)
+            }
+        ),
+        b"2020-08-29T17:51:00+0200\n".to_vec()
+    );
+
+    assert_eq!(render(&msg, "{_SELINUX_CONTEXT}", OutputOptions::default()), b"unconfined\n\n".to_vec());
+
+    let msg = JournalMessage::from_fields(vec![(b"MESSAGE".to_vec(), b"bad \x1b[2J\xff".to_vec())]);
+    assert_eq!(render(&msg, "<{MESSAGE}>", OutputOptions::default()), b"<[9B blob data]>\n".to_vec());
+    assert_eq!(
+        render(
+            &msg,
+            "<{MESSAGE}>",
+            OutputOptions {
+                all: true,
+                ..OutputOptions::default()
+            }
+        ),
+        b"<bad \\x1b[2J\\xff>\n".to_vec()
+    );
+}
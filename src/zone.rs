@@ -0,0 +1,173 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, Offset, TimeZone, Utc};
+use chrono_english::{parse_date_string, Dialect};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/* time zones
+Timestamps are shown in the local time zone like journalctl does, unless --utc or --tz picks another zone, and the
+dates given to --since and --until are read in the same zone:
+- the local zone is the one named by TZ, or by the /etc/localtime link into the zoneinfo database, which is how
+  systemd finds it. An empty TZ is UTC, as for the C library.
+- zones are looked up in the tz database built into the binary, so --tz works on systems without one. A local zone
+  that isn't in it falls back to the offset the C library reports, shown as a number instead of an abbreviation.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Zone {
+    #[default]
+    Utc,
+    Named(Tz),
+    Local,
+}
+
+impl Zone {
+    /// The time zone of the system, as set by TZ or /etc/localtime
+    pub fn local() -> Zone {
+        let name = match std::env::var("TZ") {
+            Ok(tz) if tz.trim_start_matches(':').is_empty() => return Zone::Utc,
+            Ok(tz) => tz.trim_start_matches(':').to_owned(),
+            Err(_) => match std::fs::read_link("/etc/localtime") {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(_) => return Zone::Local,
+            },
+        };
+
+        // /etc/localtime links to /usr/share/zoneinfo/Area/City, TZ may name a file in the database as well
+        let name = match name.find("zoneinfo/") {
+            Some(i) => &name[i + "zoneinfo/".len()..],
+            None => &name[..],
+        };
+        name.parse().unwrap_or(Zone::Local)
+    }
+
+    /// The wallclock time in the zone at usec microseconds since the epoch, or None when it is out of range
+    pub fn date_time(&self, usec: u64) -> Option<DateTime<FixedOffset>> {
+        let secs = usec / 1_000_000;
+        let nanos = (usec % 1_000_000) * 1_000;
+        let utc = DateTime::from_timestamp(secs as i64, nanos as u32)?.naive_utc();
+
+        let offset = match self {
+            Zone::Utc => Utc.fix(),
+            Zone::Named(tz) => tz.offset_from_utc_datetime(&utc).fix(),
+            Zone::Local => Local.offset_from_utc_datetime(&utc),
+        };
+
+        // the wallclock time has to be in range as well for formatting it
+        utc.checked_add_signed(Duration::seconds(offset.local_minus_utc() as i64))?;
+        Some(DateTime::from_naive_utc_and_offset(utc, offset))
+    }
+
+    /// Abbreviation of the zone at usec, like CET or CEST depending on daylight saving time
+    pub fn abbreviation(&self, usec: u64) -> Option<String> {
+        match self {
+            Zone::Utc => Some("UTC".to_owned()),
+            Zone::Named(tz) => {
+                let utc = DateTime::from_timestamp((usec / 1_000_000) as i64, 0)?;
+                Some(tz.offset_from_utc_datetime(&utc.naive_utc()).to_string())
+            }
+            Zone::Local => Some(self.date_time(usec)?.format("%z").to_string()),
+        }
+    }
+
+    /// Read a date for --since and --until, relative dates are relative to the current time in the zone
+    pub fn parse_date(&self, s: &str) -> Result<DateTime<Utc>, String> {
+        let parsed = match self {
+            Zone::Utc => parse_date_string(s, Utc::now(), Dialect::Us).map(|date| date.with_timezone(&Utc)),
+            Zone::Named(tz) => {
+                parse_date_string(s, Utc::now().with_timezone(tz), Dialect::Us).map(|date| date.with_timezone(&Utc))
+            }
+            Zone::Local => parse_date_string(s, Local::now(), Dialect::Us).map(|date| date.with_timezone(&Utc)),
+        };
+
+        parsed.map_err(|e| format!("Failed to parse timestamp '{}': {}", s, e))
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UTC" => Ok(Zone::Utc),
+            _ => s.parse().map(Zone::Named).map_err(|_| format!("unknown time zone '{}'", s)),
+        }
+    }
+}
+
+/// A date given to --since or --until. It's read in UTC when the argument is parsed, and read again by reread once
+/// the time zone of the output is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Date {
+    text: String,
+    time: DateTime<Utc>,
+}
+
+impl Date {
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// Read the date in the zone instead, relative dates are relative to the current time again
+    pub fn reread(&mut self, zone: &Zone) -> Result<(), String> {
+        self.time = zone.parse_date(&self.text)?;
+        Ok(())
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Date { text: s.to_owned(), time: Zone::Utc.parse_date(s)? })
+    }
+}
+
+#[test]
+fn zone_test() {
+    // 2020-10-14 04:54:59.140546 UTC, and a winter date
+    let summer = 1602651299140546;
+    let winter = 1610000000000000;
+
+    let berlin: Zone = "Europe/Berlin".parse().unwrap();
+    assert_eq!(berlin.date_time(summer).unwrap().to_rfc3339(), "2020-10-14T06:54:59.140546+02:00");
+    assert_eq!(berlin.abbreviation(summer).unwrap(), "CEST");
+    assert_eq!(berlin.abbreviation(winter).unwrap(), "CET");
+
+    let st_johns: Zone = "America/St_Johns".parse().unwrap();
+    assert_eq!(st_johns.date_time(summer).unwrap().to_rfc3339(), "2020-10-14T02:24:59.140546-02:30");
+    assert_eq!(st_johns.abbreviation(summer).unwrap(), "NDT");
+
+    assert_eq!("UTC".parse::<Zone>().unwrap(), Zone::Utc);
+    assert_eq!(Zone::Utc.date_time(summer).unwrap().to_rfc3339(), "2020-10-14T04:54:59.140546+00:00");
+    assert_eq!(Zone::Utc.abbreviation(summer).unwrap(), "UTC");
+    assert!("Nowhere/City".parse::<Zone>().is_err());
+
+    // timestamps of corrupt entries can be beyond the dates chrono represents
+    for zone in &[Zone::Utc, berlin, st_johns, Zone::Local] {
+        assert_eq!(zone.date_time(u64::MAX), None);
+    }
+    assert_eq!(berlin.abbreviation(u64::MAX), None);
+}
+
+#[test]
+fn parse_date_test() {
+    let berlin: Zone = "Europe/Berlin".parse().unwrap();
+    assert_eq!(
+        berlin.parse_date("2020-10-14 06:54:59").unwrap().to_rfc3339(),
+        "2020-10-14T04:54:59+00:00"
+    );
+    assert_eq!(
+        Zone::Utc.parse_date("2020-10-14 06:54:59").unwrap().to_rfc3339(),
+        "2020-10-14T06:54:59+00:00"
+    );
+    assert!(Zone::Utc.parse_date("not a date").is_err());
+
+    let mut date: Date = "2020-10-14 06:54:59".parse().unwrap();
+    assert_eq!(date.time().to_rfc3339(), "2020-10-14T06:54:59+00:00");
+    date.reread(&berlin).unwrap();
+    assert_eq!(date.time().to_rfc3339(), "2020-10-14T04:54:59+00:00");
+    assert!("not a date".parse::<Date>().is_err());
+}